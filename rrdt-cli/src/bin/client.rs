@@ -3,6 +3,7 @@ use rrdt_cli::recv;
 
 #[actix_rt::main]
 async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
     recv(CLIENT_ADDR, SERVER_ADDR, FILE_PATH).await?;
     Ok(())
 }
@@ -3,6 +3,7 @@ use rrdt_cli::send;
 
 #[actix_rt::main]
 async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
     send(SERVER_ADDR, FILE_PATH).await?;
     Ok(())
 }
@@ -28,7 +28,7 @@ async fn recv_random(mut conn: Connection, path: impl AsRef<Path>) -> anyhow::Re
             let mut buf = [0u8; 8 * K];
 
             // let mut total = 0;
-            println!("receiving {:?}", stream.id());
+            tracing::debug!(id = stream.id(), "receiving stream");
             loop {
                 let n = stream.recv(&mut buf).await?;
 
@@ -43,7 +43,7 @@ async fn recv_random(mut conn: Connection, path: impl AsRef<Path>) -> anyhow::Re
             }
 
             writer.flush().await?;
-            eprintln!("{:?} received", stream.id());
+            tracing::debug!(id = stream.id(), "stream received");
 
             Ok(())
         });
@@ -92,7 +92,7 @@ pub async fn recv(
     remote_addr: impl ToSocketAddrs,
     path: impl AsRef<Path>,
 ) -> anyhow::Result<()> {
-    println!("receiving");
+    tracing::info!("receiving");
 
     let path = path.as_ref();
 
@@ -105,7 +105,7 @@ pub async fn recv(
 
     match build {
         ConnectionBuildResult::Connection(conn) => {
-            recv_random(conn, path).await?;
+            recv_random(*conn, path).await?;
             Ok(())
         }
         ConnectionBuildResult::Compressed(params) => {
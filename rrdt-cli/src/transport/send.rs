@@ -23,7 +23,7 @@ async fn send_compressed(
 }
 
 pub async fn send(local_addr: impl ToSocketAddrs, path: impl AsRef<Path>) -> anyhow::Result<()> {
-    println!("sending");
+    tracing::info!("sending");
 
     let path = path.as_ref();
     if !path.exists() || !path.is_file() {
@@ -35,7 +35,7 @@ pub async fn send(local_addr: impl ToSocketAddrs, path: impl AsRef<Path>) -> any
     let len = meta.len();
     let mut reader = BufReader::new(file);
 
-    eprintln!("file size = {:?}", len);
+    tracing::info!(len, "file size");
 
     let compressed = try_compress(&mut reader).await?;
 
@@ -56,7 +56,7 @@ pub async fn send(local_addr: impl ToSocketAddrs, path: impl AsRef<Path>) -> any
             for _ in 0..stream_count {
                 let mut stream = conn.open().await;
                 let mut total = 0;
-                eprintln!("sending {:?}", stream.id());
+                tracing::debug!(id = stream.id(), "sending stream");
                 loop {
                     let n = reader.read(&mut buf).await?;
                     if n == 0 {
@@ -73,7 +73,7 @@ pub async fn send(local_addr: impl ToSocketAddrs, path: impl AsRef<Path>) -> any
                 }
 
                 stream.wrote();
-                eprintln!("{:?} sent", stream.id());
+                tracing::debug!(id = stream.id(), "stream sent");
             }
 
             conn.close().await;
@@ -1,3 +1,53 @@
+use std::time::Duration;
+
 pub const INITIAL_RTT: u64 = 333;
 pub const BASE_DATAGRAM_SIZE: u64 = 1200;
 pub const DEFAULT_LOSS_REDUCTION_FACTOR: f32 = 0.5;
+
+/// 基于时间阈值判断丢包时允许的最大乱序时间，单位为RTT
+pub const K_TIME_THRESHOLD: f32 = 9.0 / 8.0;
+
+/// 丢包跨度超过该数量的PTO时长时，认为发生了持续拥塞
+pub const K_PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
+
+/// 系统定时器的粒度
+pub const K_GRANULARITY: Duration = Duration::from_millis(1);
+
+/// HyStart++：慢启动阶段每个ack最多使窗口增长的MTU数量
+pub const HYSTART_L: u64 = 8;
+
+/// HyStart++：判断RTT增长时使用的阈值的下限
+pub const HYSTART_MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+
+/// HyStart++：判断RTT增长时使用的阈值的上限
+pub const HYSTART_MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+
+/// HyStart++：RTT增长阈值为上一轮最小RTT除以该值
+pub const HYSTART_MIN_RTT_DIVISOR: u32 = 8;
+
+/// HyStart++：每轮至少需要的RTT样本数量
+pub const HYSTART_N_RTT_SAMPLE: u32 = 8;
+
+/// HyStart++：保守慢启动阶段窗口增长速度为标准慢启动的几分之一
+pub const HYSTART_CSS_GROWTH_DIVISOR: u64 = 4;
+
+/// HyStart++：保守慢启动持续的轮数
+pub const HYSTART_CSS_ROUNDS: u32 = 5;
+
+/// LEDBAT++：目标排队时延，排队时延超过该值时缩小拥塞窗口
+pub const LEDBAT_TARGET_DELAY: Duration = Duration::from_millis(60);
+
+/// LEDBAT++：窗口增长速度相对于标准TCP的最大倒数
+pub const LEDBAT_MAX_GAIN_DIVISOR: u64 = 16;
+
+/// LEDBAT++：排队时延超过目标值的该比例时退出慢启动
+pub const LEDBAT_SLOW_START_EXIT_RATIO: f64 = 0.75;
+
+/// LEDBAT++：每个RTT内拥塞窗口最多缩小的比例
+pub const LEDBAT_MAX_DECREASE_RATIO: f64 = 0.5;
+
+/// pacing时允许一次性发出的数据量最多相当于该时长内按速率发送的数据量
+pub const PACER_MAX_BURST_INTERVAL: Duration = Duration::from_millis(2);
+
+/// 窗口化最小RTT的时间窗口，超过该时长的RTT样本不再参与最小值的计算
+pub const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
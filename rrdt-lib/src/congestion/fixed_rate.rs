@@ -0,0 +1,44 @@
+use super::{rtt_estimator::RttEstimator, Controller};
+use crate::types::PacketNum;
+use tokio::time::Instant;
+
+/// 以固定速率发送的拥塞控制，忽略丢包以及任何拥塞信号
+///
+/// 仅用于测试，在真实网络中使用会导致严重的拥塞
+#[derive(Debug, Clone)]
+pub struct FixedRate {
+    /// 发送速率，单位为字节每秒
+    rate: u64,
+}
+
+impl FixedRate {
+    pub fn new(rate: u64) -> Self {
+        Self { rate }
+    }
+}
+
+impl Controller for FixedRate {
+    fn on_sent(&mut self, _packet_num: PacketNum) {}
+
+    fn on_ack(&mut self, _packet_num: PacketNum, _sent: Instant, _bytes: u64, _rtt: &RttEstimator) {
+    }
+
+    fn on_loss(&mut self, _now: Instant, _sent: Instant, _bytes: u64) {}
+
+    fn on_ecn_ce(&mut self, _now: Instant, _sent: Instant) {}
+
+    fn on_persistent_congestion(&mut self) {}
+
+    fn on_idle_restart(&mut self) {}
+
+    fn on_mtu_update(&mut self, _mtu: u64) {}
+
+    /// 发送量只受速率限制，不受拥塞窗口限制
+    fn window(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn pacing_rate(&self, _rtt: &RttEstimator) -> u64 {
+        self.rate
+    }
+}
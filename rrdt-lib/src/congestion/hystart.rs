@@ -0,0 +1,218 @@
+use super::{constant::*, rtt_estimator::RttEstimator};
+use crate::types::PacketNum;
+use std::time::Duration;
+
+/// 基于RFC9406的HyStart++慢启动
+///
+/// 在慢启动阶段根据每一轮的最小RTT判断队列是否开始堆积，RTT明显增大时提前退出慢启动，
+/// 先进入保守慢启动（Conservative Slow Start，CSS），若RTT持续偏高则进入拥塞避免
+///
+/// 不依赖于具体的拥塞控制算法，任何拥塞控制算法都可以在慢启动阶段使用它来决定窗口的增长量
+#[derive(Debug, Clone)]
+pub struct HyStart {
+    state: State,
+
+    /// 目前为止发送过的最大packet number
+    largest_sent: PacketNum,
+
+    /// 当前轮次的结束位置，大于该值的packet被确认时进入下一轮
+    window_end: Option<PacketNum>,
+
+    last_round_min_rtt: Option<Duration>,
+    current_round_min_rtt: Option<Duration>,
+
+    /// 当前轮次收到的RTT样本数量
+    rtt_sample_count: u32,
+
+    /// 最近一次计入的RTT样本的序号，同一个ack确认的多个packet只对应一个RTT样本
+    last_sample: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    SlowStart,
+    /// 保守慢启动，`baseline_min_rtt`为进入时的最小RTT，`rounds`为已经经历的轮数
+    ConservativeSlowStart {
+        baseline_min_rtt: Duration,
+        rounds: u32,
+    },
+    /// 已经退出过慢启动，之后再次进入慢启动时使用标准的慢启动
+    Done,
+}
+
+impl HyStart {
+    pub fn new() -> Self {
+        Self {
+            state: State::SlowStart,
+            largest_sent: 0,
+            window_end: None,
+            last_round_min_rtt: None,
+            current_round_min_rtt: None,
+            rtt_sample_count: 0,
+            last_sample: 0,
+        }
+    }
+
+    pub fn on_sent(&mut self, packet_num: PacketNum) {
+        self.largest_sent = self.largest_sent.max(packet_num);
+    }
+
+    /// 在慢启动阶段处理一个被确认的packet，返回拥塞窗口的增长量
+    ///
+    /// 只有`rtt`中出现了新的RTT样本时才计入本轮的样本；返回`None`时表示应当退出慢启动，进入拥塞避免
+    pub fn on_ack(
+        &mut self,
+        packet_num: PacketNum,
+        bytes: u64,
+        rtt: &RttEstimator,
+        mtu: u64,
+    ) -> Option<u64> {
+        let increase = bytes.min(HYSTART_L * mtu);
+
+        if matches!(self.state, State::Done) {
+            return Some(increase);
+        }
+
+        if self.window_end.is_none_or(|end| packet_num > end) && !self.start_round() {
+            return None;
+        }
+
+        if rtt.samples() != self.last_sample {
+            self.last_sample = rtt.samples();
+            let rtt = rtt.latest();
+            self.current_round_min_rtt = Some(
+                self.current_round_min_rtt
+                    .map_or(rtt, |current| current.min(rtt)),
+            );
+            self.rtt_sample_count += 1;
+        }
+
+        match self.state {
+            State::SlowStart => {
+                if let Some(current) = self.delay_increased() {
+                    self.state = State::ConservativeSlowStart {
+                        baseline_min_rtt: current,
+                        rounds: 0,
+                    };
+                }
+                Some(increase)
+            }
+            State::ConservativeSlowStart {
+                baseline_min_rtt, ..
+            } => {
+                // RTT重新回落，说明之前的RTT增大只是误判，恢复慢启动
+                if self
+                    .current_round_min_rtt
+                    .is_some_and(|current| current < baseline_min_rtt)
+                {
+                    self.state = State::SlowStart;
+                    Some(increase)
+                } else {
+                    Some(increase / HYSTART_CSS_GROWTH_DIVISOR)
+                }
+            }
+            State::Done => Some(increase),
+        }
+    }
+
+    /// 发生了丢包或拥塞标记，之后由拥塞控制算法自行处理，HyStart++不再介入
+    pub fn on_congestion_event(&mut self) {
+        self.state = State::Done;
+    }
+
+    /// 进入新的一轮，返回`false`表示保守慢启动已经持续了足够多的轮数，需要进入拥塞避免
+    fn start_round(&mut self) -> bool {
+        self.window_end = Some(self.largest_sent);
+        self.last_round_min_rtt = self.current_round_min_rtt.take();
+        self.rtt_sample_count = 0;
+
+        if let State::ConservativeSlowStart { rounds, .. } = &mut self.state {
+            *rounds += 1;
+            if *rounds >= HYSTART_CSS_ROUNDS {
+                self.state = State::Done;
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// 本轮样本足够时，若最小RTT相比上一轮的增长超过阈值，返回本轮的最小RTT
+    fn delay_increased(&self) -> Option<Duration> {
+        if self.rtt_sample_count < HYSTART_N_RTT_SAMPLE {
+            return None;
+        }
+
+        let last = self.last_round_min_rtt?;
+        let current = self.current_round_min_rtt?;
+
+        let threshold =
+            (last / HYSTART_MIN_RTT_DIVISOR).clamp(HYSTART_MIN_RTT_THRESH, HYSTART_MAX_RTT_THRESH);
+
+        (current >= last + threshold).then_some(current)
+    }
+}
+
+impl Default for HyStart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_hystart() {
+    use tokio::time::Instant;
+
+    let mtu = BASE_DATAGRAM_SIZE;
+    let mut hystart = HyStart::new();
+    let mut estimator = RttEstimator::new(Duration::ZERO);
+    let mut pn = 0;
+
+    // 每一轮发送`count`个packet，每个ack确认`per_ack`个packet，返回本轮窗口的总增长量
+    let mut round = |hystart: &mut HyStart, count: u64, per_ack: u64, rtt: Duration| {
+        let start = pn;
+        for _ in 0..count {
+            hystart.on_sent(pn);
+            pn += 1;
+        }
+        (start..pn)
+            .map(|pn| {
+                if (pn - start) % per_ack == 0 {
+                    estimator.update(Duration::ZERO, rtt, Instant::now());
+                }
+                hystart.on_ack(pn, mtu, &estimator, mtu)
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|increases| increases.into_iter().sum::<u64>())
+    };
+
+    // RTT稳定时保持标准慢启动
+    for _ in 0..3 {
+        assert_eq!(
+            round(&mut hystart, 10, 1, Duration::from_millis(50)),
+            Some(10 * mtu)
+        );
+    }
+
+    // 每个ack确认多个packet时，本轮的RTT样本数量不足，不会据此退出慢启动
+    assert_eq!(
+        round(&mut hystart, 10, 5, Duration::from_millis(70)),
+        Some(10 * mtu)
+    );
+    assert!(matches!(hystart.state, State::SlowStart));
+
+    // RTT明显增大后进入保守慢启动，窗口增长放缓
+    let increase = round(&mut hystart, 10, 1, Duration::from_millis(90)).unwrap();
+    assert!(increase < 10 * mtu);
+    assert!(matches!(hystart.state, State::ConservativeSlowStart { .. }));
+
+    // 保守慢启动持续若干轮后退出慢启动
+    let mut exited = false;
+    for _ in 0..HYSTART_CSS_ROUNDS {
+        if round(&mut hystart, 10, 1, Duration::from_millis(90)).is_none() {
+            exited = true;
+            break;
+        }
+    }
+    assert!(exited);
+}
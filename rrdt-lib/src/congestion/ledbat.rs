@@ -0,0 +1,177 @@
+use super::{
+    constant::{
+        BASE_DATAGRAM_SIZE, DEFAULT_LOSS_REDUCTION_FACTOR, LEDBAT_MAX_DECREASE_RATIO,
+        LEDBAT_MAX_GAIN_DIVISOR, LEDBAT_SLOW_START_EXIT_RATIO, LEDBAT_TARGET_DELAY,
+    },
+    rtt_estimator::RttEstimator,
+    Controller,
+};
+use crate::types::PacketNum;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 基于RFC6817以及LEDBAT++的低优先级拥塞控制
+///
+/// 以[`RttEstimator`]观测到的窗口化最小RTT作为基础时延，最近一次RTT样本与其之差即为排队时延。
+/// 排队时延低于目标值时缓慢增长拥塞窗口，超过目标值时按超出的比例缩小拥塞窗口，
+/// 从而在与其他流量共享瓶颈时主动让出带宽
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    current_mtu: u64,
+
+    /// 拥塞窗口，拥塞避免阶段每个ack带来的变化量可能小于一个字节，因此使用浮点数保存
+    window: f64,
+
+    initial_window: u64,
+
+    /// 是否处于慢启动阶段，排队时延接近目标值或发生拥塞事件后退出
+    slow_start: bool,
+
+    /// 第一次检测到拥塞事件时的时间，为`None`时表示当前不处于恢复状态
+    recovery_start_time: Option<Instant>,
+}
+
+impl Ledbat {
+    pub fn new(now: Instant, current_mtu: u16) -> Self {
+        let current_mtu = current_mtu as u64;
+        let initial_window = 2 * current_mtu;
+        Self {
+            current_mtu,
+            window: initial_window as f64,
+            initial_window,
+            slow_start: true,
+            recovery_start_time: Some(now),
+        }
+    }
+
+    fn in_recovery(&self, sent: Instant) -> bool {
+        self.recovery_start_time
+            .is_some_and(|recovery_start_time| sent <= recovery_start_time)
+    }
+
+    /// 窗口增长速度相对于标准TCP的比例：1 / min(16, ceil(2 * target / base_delay))
+    ///
+    /// 基础时延越小，增长越慢，避免在低时延链路上过于激进
+    fn gain(base_delay: Duration) -> f64 {
+        let divisor = if base_delay.is_zero() {
+            LEDBAT_MAX_GAIN_DIVISOR as f64
+        } else {
+            (2.0 * LEDBAT_TARGET_DELAY.as_secs_f64() / base_delay.as_secs_f64())
+                .ceil()
+                .clamp(1.0, LEDBAT_MAX_GAIN_DIVISOR as f64)
+        };
+        1.0 / divisor
+    }
+
+    fn minimum_window(&self) -> f64 {
+        (2 * self.current_mtu) as f64
+    }
+
+    fn on_congestion_event(&mut self, now: Instant, sent: Instant) {
+        if self.in_recovery(sent) {
+            return;
+        }
+
+        self.recovery_start_time = Some(now);
+        self.slow_start = false;
+        self.window =
+            (self.window * DEFAULT_LOSS_REDUCTION_FACTOR as f64).max(self.minimum_window());
+    }
+}
+
+impl Controller for Ledbat {
+    fn on_sent(&mut self, _packet_num: PacketNum) {}
+
+    fn on_ack(&mut self, _packet_num: PacketNum, sent: Instant, bytes: u64, rtt: &RttEstimator) {
+        if self.in_recovery(sent) {
+            return;
+        }
+
+        let base_delay = rtt.windowed_min();
+        let queuing_delay = rtt.latest().saturating_sub(base_delay);
+        let gain = Self::gain(base_delay);
+        let bytes = bytes as f64;
+
+        // 排队时延与目标值的比值
+        let off_target = queuing_delay.as_secs_f64() / LEDBAT_TARGET_DELAY.as_secs_f64();
+
+        if self.slow_start {
+            if off_target < LEDBAT_SLOW_START_EXIT_RATIO {
+                self.window += gain * bytes;
+                return;
+            }
+            self.slow_start = false;
+        }
+
+        // 每个RTT窗口增长gain个MTU，排队时延超过目标值时按超出的比例缩小，但每个RTT最多缩小一半
+        let decrease = (off_target - 1.0).clamp(0.0, LEDBAT_MAX_DECREASE_RATIO);
+        let mtu = self.current_mtu as f64;
+        self.window += gain * mtu * bytes / self.window - decrease * bytes;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn on_loss(&mut self, now: Instant, sent: Instant, _bytes: u64) {
+        self.on_congestion_event(now, sent);
+    }
+
+    fn on_ecn_ce(&mut self, now: Instant, sent: Instant) {
+        self.on_congestion_event(now, sent);
+    }
+
+    fn on_persistent_congestion(&mut self) {
+        self.window = self.minimum_window();
+        self.recovery_start_time = None;
+    }
+
+    fn on_idle_restart(&mut self) {
+        self.window = self.window.min(self.initial_window as f64);
+    }
+
+    fn on_mtu_update(&mut self, mtu: u64) {
+        self.current_mtu = mtu;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window as u64
+    }
+}
+
+impl Default for Ledbat {
+    fn default() -> Self {
+        Self::new(Instant::now(), BASE_DATAGRAM_SIZE as u16)
+    }
+}
+
+#[test]
+fn test_ledbat() {
+    let mtu = BASE_DATAGRAM_SIZE;
+    let start = Instant::now();
+    let mut ledbat = Ledbat::new(start, mtu as u16);
+    let mut rtt = RttEstimator::new(Duration::ZERO);
+    let mut pn = 0;
+
+    let mut ack = |ledbat: &mut Ledbat, rtt: &mut RttEstimator, sample: Duration| {
+        pn += 1;
+        rtt.update(Duration::ZERO, sample, start + Duration::from_millis(pn));
+        ledbat.on_ack(pn, start + Duration::from_millis(pn), mtu, rtt);
+    };
+
+    // 没有排队时延时窗口持续增长
+    for _ in 0..100 {
+        ack(&mut ledbat, &mut rtt, Duration::from_millis(40));
+    }
+    let window = ledbat.window();
+    assert!(window > 2 * mtu);
+
+    // 排队时延超过目标值后退出慢启动并缩小窗口
+    for _ in 0..100 {
+        ack(
+            &mut ledbat,
+            &mut rtt,
+            Duration::from_millis(40) + 2 * LEDBAT_TARGET_DELAY,
+        );
+    }
+    assert!(!ledbat.slow_start);
+    assert!(ledbat.window() < window);
+}
@@ -1,110 +1,74 @@
 mod constant;
+pub mod fixed_rate;
+pub mod hystart;
+pub mod ledbat;
+pub mod new_reno;
+pub mod pacer;
 pub mod rtt_estimator;
+pub mod windowed_min;
 
-use self::constant::{BASE_DATAGRAM_SIZE, DEFAULT_LOSS_REDUCTION_FACTOR};
+use self::rtt_estimator::RttEstimator;
+use crate::types::PacketNum;
+use std::fmt::Debug;
 use tokio::time::Instant;
 
-#[derive(Debug, Clone)]
-pub struct NewReno {
-    config: NewRenoConfig,
-    current_mtu: u64,
+pub use self::{fixed_rate::FixedRate, ledbat::Ledbat, new_reno::NewReno, pacer::Pacer};
 
-    /// 拥塞窗口，也即同一时间内允许正在传输的最大数据量
-    window: u64,
+/// 拥塞控制算法，决定同一时间内允许正在传输的最大数据量
+///
+/// 由Sender在packet发送、被确认以及丢失时驱动，StreamsInner根据拥塞窗口进行pacing
+pub trait Controller: Debug + Send + Sync {
+    /// 发出了一个packet
+    fn on_sent(&mut self, packet_num: PacketNum);
 
-    /// 慢启动阈值，当拥塞窗口小于ssthresh时，处于慢启动状态，拥塞窗口增长的速度为已确认的数据量
-    ///
-    /// 当拥塞窗口大于ssthresh时，处于拥塞避免状态，拥塞窗口增长的速度为已确认的数据量除以拥塞窗口大小
-    ssthresh: u64,
-
-    /// 第一次检测到丢包时的时间，当收到一个在这个时间之后发送的数据包的确认时，退出恢复状态
-    recovery_start_time: Instant,
-
-    /// 在离开慢启动状态后，已被对端确认的数据量
-    bytes_acked: u64,
-}
-
-impl NewReno {
-    pub fn new(config: NewRenoConfig, now: Instant, current_mtu: u16) -> Self {
-        Self {
-            window: config.initial_window,
-            ssthresh: u64::max_value(),
-            recovery_start_time: now,
-            current_mtu: current_mtu as u64,
-            config,
-            bytes_acked: 0,
-        }
-    }
-
-    pub fn on_ack(&mut self, sent: Instant, bytes: u64) {
-        if sent <= self.recovery_start_time {
-            return;
-        }
-
-        if self.window < self.ssthresh {
-            // 慢启动
-            self.window += bytes;
+    /// 一个在`sent`时发送、大小为`bytes`的packet被对端确认
+    fn on_ack(&mut self, packet_num: PacketNum, sent: Instant, bytes: u64, rtt: &RttEstimator);
 
-            if self.window >= self.ssthresh {
-                // 退出慢启动
-                self.bytes_acked = self.window - self.ssthresh;
-            }
-        } else {
-            // 拥塞避免
-            self.bytes_acked += bytes;
+    /// 发生了丢包，`sent`为丢失的packet中最晚的发送时间
+    fn on_loss(&mut self, now: Instant, sent: Instant, bytes: u64);
 
-            if self.bytes_acked >= self.window {
-                self.bytes_acked -= self.window;
-                self.window += self.current_mtu;
-            }
-        }
-    }
+    /// 对端报告了新的ECN CE标记
+    fn on_ecn_ce(&mut self, now: Instant, sent: Instant);
 
-    /// 发生了丢包
-    pub fn on_loss(&mut self, now: Instant, sent: Instant, _bytes: u64) {
-        if sent <= self.recovery_start_time {
-            return;
-        }
-
-        self.recovery_start_time = now;
-        self.window = (self.window as f32 * self.config.loss_reduction_factor) as u64;
-        self.window = self.window.max(self.minimum_window());
-        self.ssthresh = self.window;
-    }
+    /// 发生了持续拥塞
+    fn on_persistent_congestion(&mut self);
 
-    pub fn window(&self) -> u64 {
-        self.window
-    }
+    /// 连接空闲了一段时间后重新开始发送
+    fn on_idle_restart(&mut self);
 
-    pub fn initial_window(&self) -> u64 {
-        self.config.initial_window
-    }
+    /// 路径MTU探测确认了更大的datagram大小
+    fn on_mtu_update(&mut self, mtu: u64);
 
-    pub fn minimum_window(&self) -> u64 {
-        2 * self.current_mtu
-    }
-}
+    /// 当前的拥塞窗口
+    fn window(&self) -> u64;
 
-impl Default for NewReno {
-    fn default() -> Self {
-        let config = NewRenoConfig::default();
-        let now = Instant::now();
-        let mtu = 1200;
-        Self::new(config, now, mtu)
+    /// 期望的发送速率，单位为字节每秒
+    ///
+    /// 默认每个RTT发送1.25倍的拥塞窗口，略高于窗口使得拥塞窗口能够被充分利用
+    fn pacing_rate(&self, rtt: &RttEstimator) -> u64 {
+        let rtt = (rtt.rtt().as_micros() as u64).max(1);
+        let window = self.window().saturating_add(self.window() / 4);
+        window.saturating_mul(1_000_000) / rtt
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct NewRenoConfig {
-    pub initial_window: u64,
-    pub loss_reduction_factor: f32,
+/// 连接可以选用的拥塞控制算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    /// 低优先级的后台传输，排队时延升高时主动让出带宽，见[`Ledbat`]
+    Ledbat,
+    /// 以固定速率（字节每秒）发送并忽略丢包，仅用于测试，见[`FixedRate`]
+    FixedRate(u64),
 }
 
-impl Default for NewRenoConfig {
-    fn default() -> Self {
-        Self {
-            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
-            loss_reduction_factor: DEFAULT_LOSS_REDUCTION_FACTOR,
+impl CongestionAlgorithm {
+    pub(crate) fn build(self) -> Box<dyn Controller> {
+        match self {
+            Self::NewReno => Box::<NewReno>::default(),
+            Self::Ledbat => Box::<Ledbat>::default(),
+            Self::FixedRate(rate) => Box::new(FixedRate::new(rate)),
         }
     }
 }
@@ -0,0 +1,169 @@
+use super::{
+    constant::{BASE_DATAGRAM_SIZE, DEFAULT_LOSS_REDUCTION_FACTOR},
+    hystart::HyStart,
+    rtt_estimator::RttEstimator,
+    Controller,
+};
+use crate::types::PacketNum;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct NewReno {
+    config: NewRenoConfig,
+    current_mtu: u64,
+
+    /// 拥塞窗口，也即同一时间内允许正在传输的最大数据量
+    window: u64,
+
+    /// 慢启动阈值，当拥塞窗口小于ssthresh时，处于慢启动状态，拥塞窗口增长的速度为已确认的数据量
+    ///
+    /// 当拥塞窗口大于ssthresh时，处于拥塞避免状态，拥塞窗口增长的速度为已确认的数据量除以拥塞窗口大小
+    ssthresh: u64,
+
+    /// 第一次检测到丢包时的时间，当收到一个在这个时间之后发送的数据包的确认时，退出恢复状态
+    ///
+    /// 为`None`时表示当前不处于恢复状态
+    recovery_start_time: Option<Instant>,
+
+    /// 在离开慢启动状态后，已被对端确认的数据量
+    bytes_acked: u64,
+
+    /// 慢启动阶段由HyStart++决定窗口增长量以及何时提前退出慢启动
+    hystart: HyStart,
+}
+
+impl NewReno {
+    pub fn new(config: NewRenoConfig, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window,
+            ssthresh: u64::max_value(),
+            recovery_start_time: Some(now),
+            current_mtu: current_mtu as u64,
+            config,
+            bytes_acked: 0,
+            hystart: HyStart::new(),
+        }
+    }
+
+    fn in_recovery(&self, sent: Instant) -> bool {
+        self.recovery_start_time
+            .is_some_and(|recovery_start_time| sent <= recovery_start_time)
+    }
+
+    /// 每个恢复周期内最多缩小一次拥塞窗口
+    fn on_congestion_event(&mut self, now: Instant, sent: Instant) {
+        if self.in_recovery(sent) {
+            return;
+        }
+
+        self.recovery_start_time = Some(now);
+        self.hystart.on_congestion_event();
+        self.window = (self.window as f32 * self.config.loss_reduction_factor) as u64;
+        self.window = self.window.max(self.minimum_window());
+        self.ssthresh = self.window;
+    }
+
+    pub fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    pub fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+}
+
+impl Controller for NewReno {
+    fn on_sent(&mut self, packet_num: PacketNum) {
+        self.hystart.on_sent(packet_num);
+    }
+
+    fn on_ack(&mut self, packet_num: PacketNum, sent: Instant, bytes: u64, rtt: &RttEstimator) {
+        if self.in_recovery(sent) {
+            return;
+        }
+
+        if self.window < self.ssthresh {
+            // 慢启动
+            match self
+                .hystart
+                .on_ack(packet_num, bytes, rtt, self.current_mtu)
+            {
+                Some(increase) => {
+                    self.window += increase;
+
+                    if self.window >= self.ssthresh {
+                        // 退出慢启动
+                        self.bytes_acked = self.window - self.ssthresh;
+                    }
+                }
+                // HyStart++判断队列已经开始堆积，提前退出慢启动
+                None => {
+                    self.ssthresh = self.window;
+                    self.bytes_acked = 0;
+                }
+            }
+        } else {
+            // 拥塞避免
+            self.bytes_acked += bytes;
+
+            if self.bytes_acked >= self.window {
+                self.bytes_acked -= self.window;
+                self.window += self.current_mtu;
+            }
+        }
+    }
+
+    /// 发生了丢包
+    fn on_loss(&mut self, now: Instant, sent: Instant, _bytes: u64) {
+        self.on_congestion_event(now, sent);
+    }
+
+    /// 对端报告了新的ECN CE标记，与丢包一样缩小拥塞窗口，但不需要重传任何数据
+    fn on_ecn_ce(&mut self, now: Instant, sent: Instant) {
+        self.on_congestion_event(now, sent);
+    }
+
+    /// 发生了持续拥塞，网络可能经历了长时间的中断，将拥塞窗口降至最小值并重新慢启动
+    fn on_persistent_congestion(&mut self) {
+        self.window = self.minimum_window();
+        self.recovery_start_time = None;
+    }
+
+    /// 连接空闲了一段时间后重新开始发送，将拥塞窗口重置为不超过初始窗口
+    fn on_idle_restart(&mut self) {
+        self.window = self.window.min(self.initial_window());
+    }
+
+    fn on_mtu_update(&mut self, mtu: u64) {
+        self.current_mtu = mtu;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+}
+
+impl Default for NewReno {
+    fn default() -> Self {
+        let config = NewRenoConfig::default();
+        let now = Instant::now();
+        let mtu = 1200;
+        Self::new(config, now, mtu)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewRenoConfig {
+    pub initial_window: u64,
+    pub loss_reduction_factor: f32,
+}
+
+impl Default for NewRenoConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            loss_reduction_factor: DEFAULT_LOSS_REDUCTION_FACTOR,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use super::constant::{BASE_DATAGRAM_SIZE, PACER_MAX_BURST_INTERVAL};
+use tokio::time::Instant;
+
+/// 基于令牌桶的pacer，将拥塞控制给出的发送速率平滑地分摊到时间上
+///
+/// 设置了`max_send_rate`时，实际的发送速率不会超过该值
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    /// 发送速率上限，单位为字节每秒
+    max_send_rate: Option<u64>,
+
+    /// 当前可以发送的数据量
+    tokens: f64,
+
+    /// 上一次补充令牌的时间
+    last_refill: Instant,
+}
+
+impl Pacer {
+    pub fn new(max_send_rate: Option<u64>, now: Instant) -> Self {
+        Self {
+            max_send_rate,
+            tokens: 0.0,
+            last_refill: now,
+        }
+    }
+
+    pub fn max_send_rate(&self) -> Option<u64> {
+        self.max_send_rate
+    }
+
+    pub fn set_max_send_rate(&mut self, max_send_rate: Option<u64>) {
+        self.max_send_rate = max_send_rate;
+    }
+
+    /// 以速率`rate`（字节每秒）补充令牌，返回此刻允许发送的数据量
+    ///
+    /// 为避免发出过小的packet，令牌不足一个datagram时先积攒起来
+    pub fn budget(&mut self, rate: u64, now: Instant) -> usize {
+        let rate = self.max_send_rate.map_or(rate, |max| rate.min(max)) as f64;
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+
+        let burst = (rate * PACER_MAX_BURST_INTERVAL.as_secs_f64()).max(BASE_DATAGRAM_SIZE as f64);
+        self.tokens = (self.tokens + rate * elapsed.as_secs_f64()).min(burst);
+
+        if self.tokens < BASE_DATAGRAM_SIZE as f64 {
+            return 0;
+        }
+
+        let bytes = self.tokens as usize;
+        self.tokens -= bytes as f64;
+        bytes
+    }
+}
+
+#[test]
+fn test_pacer() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut pacer = Pacer::new(Some(100_000), start);
+
+    // 速率上限为100KB/s，1秒内最多发送约100KB
+    let total: usize = (1..=1000)
+        .map(|ms| pacer.budget(u64::MAX, start + Duration::from_millis(ms)))
+        .sum();
+    assert!(total <= 100_000 && total > 95_000);
+
+    // 取消上限后按照拥塞控制给出的速率发送
+    pacer.set_max_send_rate(None);
+    let total: usize = (1001..=2000)
+        .map(|ms| pacer.budget(1_000_000, start + Duration::from_millis(ms)))
+        .sum();
+    assert!(total <= 1_000_000 && total > 950_000);
+}
@@ -1,5 +1,15 @@
-use super::constant::INITIAL_RTT;
-use std::{cmp::min, time::Duration};
+use super::{
+    constant::{
+        INITIAL_RTT, K_GRANULARITY, K_PERSISTENT_CONGESTION_THRESHOLD, K_TIME_THRESHOLD,
+        MIN_RTT_WINDOW,
+    },
+    windowed_min::WindowedMinFilter,
+};
+use std::{
+    cmp::{max, min},
+    time::Duration,
+};
+use tokio::time::Instant;
 
 /// 基于RFC6298的RTT估计器
 pub struct RttEstimator {
@@ -7,7 +17,11 @@ pub struct RttEstimator {
     smoothed: Option<Duration>,
     var: Duration,
     min: Duration,
+    /// 最近一段时间内的最小RTT，路径发生变化后旧的最小值会逐渐过期
+    windowed_min: WindowedMinFilter,
     max_ack_delay: Duration,
+    /// 目前为止收到的RTT样本数量，每个ack最多带来一个样本
+    samples: u64,
 }
 
 impl RttEstimator {
@@ -18,7 +32,9 @@ impl RttEstimator {
             smoothed: None,
             var: initial_rtt / 2,
             min: initial_rtt,
+            windowed_min: WindowedMinFilter::new(MIN_RTT_WINDOW),
             max_ack_delay,
+            samples: 0,
         }
     }
 
@@ -26,13 +42,55 @@ impl RttEstimator {
         self.smoothed.unwrap_or(self.latest)
     }
 
+    /// 最近一次的RTT样本
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
+    /// 目前为止收到的RTT样本数量，可以用来判断[`latest`](Self::latest)是否是新的样本
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// 最近`MIN_RTT_WINDOW`时间内观测到的最小RTT
+    pub fn windowed_min(&self) -> Duration {
+        self.windowed_min.get().unwrap_or(self.min)
+    }
+
+    pub fn stats(&self) -> RttStats {
+        RttStats {
+            latest: self.latest,
+            smoothed: self.rtt(),
+            var: self.var,
+            min: self.min,
+            windowed_min: self.windowed_min(),
+        }
+    }
+
     /// RTO = smoothed_rtt + max(4 * rttvar, kGranularity) + ack_delay
     pub fn rto(&self) -> Duration {
-        self.rtt() + Duration::from_micros(4 * self.var.as_micros() as u64) + self.max_ack_delay
+        self.rtt() + max(4 * self.var, K_GRANULARITY) + self.max_ack_delay
+    }
+
+    /// 持续拥塞时长：(smoothed_rtt + max(4 * rttvar, kGranularity) + max_ack_delay) * kPersistentCongestionThreshold
+    pub fn persistent_congestion_duration(&self) -> Duration {
+        self.rto() * K_PERSISTENT_CONGESTION_THRESHOLD
     }
 
-    pub(crate) fn update(&mut self, ack_delay: Duration, rtt: Duration) {
+    /// 基于时间阈值判断丢包的延迟：max(kTimeThreshold * max(smoothed_rtt, latest_rtt), kGranularity)
+    ///
+    /// 早于最大已确认packet发送、且发送时间距今超过该延迟的packet被认为丢失
+    pub fn loss_delay(&self) -> Duration {
+        let rtt = max(self.rtt(), self.latest);
+        max(rtt.mul_f32(K_TIME_THRESHOLD), K_GRANULARITY)
+    }
+
+    pub(crate) fn update(&mut self, ack_delay: Duration, rtt: Duration, now: Instant) {
         self.latest = rtt;
+        self.samples += 1;
+        self.windowed_min.update(now, rtt);
+        // 对端承诺的ack延迟不会超过max_ack_delay，超出的部分视为路径上的时延
+        let ack_delay = min(ack_delay, self.max_ack_delay);
 
         self.min = min(self.min, self.latest);
 
@@ -56,3 +114,18 @@ impl RttEstimator {
         }
     }
 }
+
+/// 连接的RTT统计信息
+#[derive(Debug, Clone, Copy)]
+pub struct RttStats {
+    /// 最近一次的RTT样本
+    pub latest: Duration,
+    /// 平滑后的RTT，还没有RTT样本时为初始RTT
+    pub smoothed: Duration,
+    /// RTT的平均偏差
+    pub var: Duration,
+    /// 连接建立以来的最小RTT
+    pub min: Duration,
+    /// 最近一段时间内的最小RTT
+    pub windowed_min: Duration,
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 基于Kathleen Nichols算法的窗口化最小值过滤器，与Linux中的`minmax`相同
+///
+/// 只保存窗口内最小、次小、第三小的三个样本，在常数时间和空间内追踪最近一段时间内的最小值
+#[derive(Debug, Clone)]
+pub struct WindowedMinFilter {
+    window: Duration,
+    samples: Option<[Sample; 3]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    time: Instant,
+    value: Duration,
+}
+
+impl WindowedMinFilter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: None,
+        }
+    }
+
+    /// 窗口内的最小值，还没有任何样本时为`None`
+    pub fn get(&self) -> Option<Duration> {
+        self.samples.map(|samples| samples[0].value)
+    }
+
+    pub fn update(&mut self, time: Instant, value: Duration) {
+        let sample = Sample { time, value };
+
+        let Some(samples) = &mut self.samples else {
+            self.samples = Some([sample; 3]);
+            return;
+        };
+
+        // 新的最小值，或者所有样本都已经过期
+        if value <= samples[0].value || time - samples[2].time > self.window {
+            *samples = [sample; 3];
+            return;
+        }
+
+        if value <= samples[1].value {
+            samples[1] = sample;
+            samples[2] = sample;
+        } else if value <= samples[2].value {
+            samples[2] = sample;
+        }
+
+        // 最小值过期时依次用次小值和第三小值替代，并在窗口的1/4和1/2处刷新次小值和第三小值
+        let elapsed = time - samples[0].time;
+        if elapsed > self.window {
+            samples.rotate_left(1);
+            samples[2] = sample;
+            if time - samples[0].time > self.window {
+                samples.rotate_left(1);
+                samples[2] = sample;
+            }
+        } else if samples[1].time == samples[0].time && elapsed > self.window / 4 {
+            samples[1] = sample;
+            samples[2] = sample;
+        } else if samples[2].time == samples[1].time && elapsed > self.window / 2 {
+            samples[2] = sample;
+        }
+    }
+}
+
+#[test]
+fn test_windowed_min() {
+    let start = Instant::now();
+    let window = Duration::from_secs(10);
+    let mut filter = WindowedMinFilter::new(window);
+    let ms = Duration::from_millis;
+
+    filter.update(start, ms(50));
+    filter.update(start + Duration::from_secs(1), ms(80));
+    assert_eq!(filter.get(), Some(ms(50)));
+
+    // 路径变长后，旧的最小值在窗口过后失效
+    for secs in 2..=12 {
+        filter.update(start + Duration::from_secs(secs), ms(80));
+    }
+    assert_eq!(filter.get(), Some(ms(80)));
+}
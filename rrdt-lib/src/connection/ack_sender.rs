@@ -3,9 +3,10 @@ use super::ConnectionContext;
 use super::{constant::*, packetizer};
 use crate::{
     frame::{
-        ack::{AckFrame, AckSpans},
+        ack::{AckFrame, AckSpans, EcnCounts},
         Frame,
     },
+    socket::EcnCodepoint,
     types::PacketNum,
 };
 use actix::prelude::*;
@@ -20,6 +21,9 @@ pub struct AckSender {
     /// 当前已经收到的所有packet
     spans: AckSpans,
 
+    /// 当前已经收到的所有packet中各类ECN标记的数量
+    ecn: EcnCounts,
+
     /// 当前已经ack过的最大packet number
     ///
     /// 收到小于等于该值的ack_eliciting packet时，认为是乱序包，立即发送ack frame
@@ -41,6 +45,7 @@ impl AckSender {
             addrs,
             state: State::Idle,
             spans: AckSpans::new(),
+            ecn: EcnCounts::default(),
             acked: 0,
             wait_count: DEFAULT_ACK_WAIT_COUNT,
             timeout_handle: None,
@@ -60,7 +65,12 @@ impl AckSender {
     /// 发送ack frame
     fn send_with_delay(&self, delay: Duration) {
         let mut frame: AckFrame = self.spans.clone().into();
-        frame.set_delay(delay);
+        frame.set_delay(delay, self.ctx.local_params.ack_delay_exponent);
+
+        // 收到过ECN标记的packet时才需要发送ACK_ECN
+        if !self.ecn.is_empty() {
+            frame = frame.with_ecn(self.ecn);
+        }
 
         self.addrs
             .packetizer
@@ -80,11 +90,15 @@ impl Handler<Recv> for AckSender {
         Recv {
             packet_num,
             is_ack_eliciting,
+            ecn,
             instant,
         }: Recv,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.spans.insert(packet_num);
+        // 重复收到的packet不应该被重复计入ECN计数
+        if self.spans.insert(packet_num) {
+            self.ecn.record(ecn);
+        }
 
         // 如果不是ack_eliciting的包，选择性更新acked即可
         if !is_ack_eliciting {
@@ -155,6 +169,7 @@ impl Handler<Timeout> for AckSender {
 pub struct Recv {
     pub packet_num: PacketNum,
     pub is_ack_eliciting: bool,
+    pub ecn: EcnCodepoint,
     pub instant: Instant,
 }
 
@@ -0,0 +1,100 @@
+use super::constant::AMPLIFICATION_FACTOR;
+
+/// 抗放大攻击的发送限制，见RFC9000 8.1节
+///
+/// 在对端地址被验证之前，发往对端的数据量不能超过从对端收到的数据量的3倍，
+/// 避免伪造源地址的握手将服务端变成放大器
+#[derive(Debug, Clone)]
+pub struct AmplificationLimit {
+    received: u64,
+    sent: u64,
+    /// 超出限制而暂缓发送的数据量，同样占用限额，避免继续产生新的数据
+    queued: u64,
+    validated: bool,
+}
+
+impl AmplificationLimit {
+    /// 对端地址已经验证过，不受限制
+    pub fn validated() -> Self {
+        Self {
+            received: 0,
+            sent: 0,
+            queued: 0,
+            validated: true,
+        }
+    }
+
+    /// 对端地址尚未验证，`received`和`sent`为握手阶段已经收发的数据量
+    pub fn unvalidated(received: u64, sent: u64) -> Self {
+        Self {
+            received,
+            sent,
+            queued: 0,
+            validated: false,
+        }
+    }
+
+    pub fn is_validated(&self) -> bool {
+        self.validated
+    }
+
+    /// 确认对端能够收到本端发出的数据，解除限制
+    pub fn validate(&mut self) {
+        self.validated = true;
+    }
+
+    pub fn on_received(&mut self, bytes: u64) {
+        self.received += bytes;
+    }
+
+    pub fn on_sent(&mut self, bytes: u64) {
+        self.sent += bytes;
+    }
+
+    /// 超出限制的数据暂缓发送
+    pub fn on_queued(&mut self, bytes: u64) {
+        self.queued += bytes;
+    }
+
+    /// 尝试发出一段暂缓的数据，仍然超出限制时返回`false`
+    pub fn release(&mut self, bytes: u64) -> bool {
+        if !self.validated && self.sent + bytes > self.received * AMPLIFICATION_FACTOR {
+            return false;
+        }
+        self.queued -= bytes;
+        self.sent += bytes;
+        true
+    }
+
+    /// 此刻还允许发送的数据量，地址已验证时为`None`
+    pub fn allowance(&self) -> Option<u64> {
+        if self.validated {
+            None
+        } else {
+            Some((self.received * AMPLIFICATION_FACTOR).saturating_sub(self.sent + self.queued))
+        }
+    }
+}
+
+#[test]
+fn test_amplification_limit() {
+    let mut limit = AmplificationLimit::unvalidated(1200, 100);
+    assert_eq!(limit.allowance(), Some(3500));
+
+    limit.on_sent(3500);
+    assert_eq!(limit.allowance(), Some(0));
+
+    // 收到更多数据后可以继续发送，地址验证后不再限制
+    limit.on_received(100);
+    assert_eq!(limit.allowance(), Some(300));
+
+    // 暂缓的数据占用限额，收到足够的数据之后才能发出
+    limit.on_queued(1000);
+    assert_eq!(limit.allowance(), Some(0));
+    assert!(!limit.release(1000));
+    limit.on_received(300);
+    assert!(limit.release(1000));
+    assert_eq!(limit.allowance(), Some(200));
+    limit.validate();
+    assert_eq!(limit.allowance(), None);
+}
@@ -16,8 +16,26 @@ pub struct ListenAckedBcast(pub Recipient<AckedBcast>);
 /// 有新的packet丢失
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct LostBcast(pub PacketMeta);
+pub struct LostBcast {
+    /// 此次被判定为丢失的packet，按packet number排序
+    pub packets: Vec<PacketMeta>,
+
+    /// 丢失的packet是否表明发生了持续拥塞
+    pub persistent_congestion: bool,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ListenLostBcast(pub Recipient<LostBcast>);
+
+/// 探测超时（PTO）到期，需要发送探测包
+///
+/// 携带当前最早发送的若干个仍在传输中的packet，每个packet对应一个只包含PING的探测包。
+/// 探测包不重传数据，数据只在packet被判定丢失后重传
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ProbeBcast(pub Vec<PacketMeta>);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ListenProbeBcast(pub Recipient<ProbeBcast>);
@@ -0,0 +1,89 @@
+use super::constant::DEFAULT_KEY_UPDATE_INTERVAL;
+use crate::{congestion::CongestionAlgorithm, packet::MAX_PACKET_SIZE};
+use rand::{rngs::StdRng, SeedableRng};
+use std::path::PathBuf;
+
+/// 连接本地使用的传输配置
+///
+/// 与[`TransportParams`](super::TransportParams)不同，这些配置只影响本端的行为，不会发送给对端
+#[derive(Clone, Debug)]
+pub struct TransportConfig {
+    /// 拥塞控制算法
+    pub congestion: CongestionAlgorithm,
+
+    /// 发送速率上限，单位为字节每秒，在拥塞窗口的基础上进一步限制发送速率
+    pub max_send_rate: Option<u64>,
+
+    /// 同一代密钥最多保护多少个packet，达到后自动更新密钥，`None`表示不自动更新
+    ///
+    /// 只在加密的连接上生效
+    pub key_update_interval: Option<u64>,
+
+    /// 发出的datagram至少为多少字节，不足时使用PADDING frame填充，`None`表示不填充
+    pub min_datagram_size: Option<usize>,
+
+    /// 连接中所有随机行为使用的种子，`None`表示使用系统熵源
+    ///
+    /// 相同的种子配合[`simulate`](crate::testing::simulate)可以完全复现一次运行。
+    /// 握手的随机数也由它决定，只应当在测试中使用
+    pub seed: Option<u64>,
+
+    /// 在该目录下为每个连接写入`<连接ID>.sqlog`，记录qlog格式的事件，`None`表示不记录
+    ///
+    /// 生成的文件可以直接在qvis中打开
+    pub qlog_dir: Option<PathBuf>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            congestion: CongestionAlgorithm::default(),
+            max_send_rate: None,
+            key_update_interval: Some(DEFAULT_KEY_UPDATE_INTERVAL),
+            min_datagram_size: None,
+            seed: None,
+            qlog_dir: None,
+        }
+    }
+}
+
+impl TransportConfig {
+    pub fn with_congestion(mut self, congestion: CongestionAlgorithm) -> Self {
+        self.congestion = congestion;
+        self
+    }
+
+    pub fn with_max_send_rate(mut self, max_send_rate: u64) -> Self {
+        self.max_send_rate = Some(max_send_rate);
+        self
+    }
+
+    pub fn with_key_update_interval(mut self, key_update_interval: Option<u64>) -> Self {
+        self.key_update_interval = key_update_interval;
+        self
+    }
+
+    /// 最大不超过[`MAX_PACKET_SIZE`]
+    pub fn with_min_datagram_size(mut self, min_datagram_size: usize) -> Self {
+        self.min_datagram_size = Some(min_datagram_size.min(MAX_PACKET_SIZE));
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_qlog_dir(mut self, qlog_dir: impl Into<PathBuf>) -> Self {
+        self.qlog_dir = Some(qlog_dir.into());
+        self
+    }
+
+    /// 根据种子创建连接使用的随机数生成器
+    pub(crate) fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
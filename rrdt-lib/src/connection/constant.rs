@@ -7,3 +7,45 @@ pub const DEFAULT_ACK_WAIT_COUNT: u64 = 2;
 pub const MAX_PACKET_DELAY: Duration = Duration::from_millis(25);
 
 pub const DEFAULT_MAX_ACK_DELAY: Duration = Duration::from_millis(100);
+
+/// ack延迟编码时默认使用的指数，即默认以8微秒为单位
+pub const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+
+/// ack_delay_exponent允许的最大值
+pub const MAX_ACK_DELAY_EXPONENT: u8 = 20;
+
+/// 包序阈值：某个packet之后发送的packet被确认，且两者包号相差达到或超过该值时，认为该packet丢失
+pub const K_PACKET_THRESHOLD: u64 = 3;
+
+/// PTO到期时最多发送的探测包数量
+pub const K_MAX_PROBES: usize = 2;
+
+/// 默认每发送2^20个packet自动更新一次密钥
+pub const DEFAULT_KEY_UPDATE_INTERVAL: u64 = 1 << 20;
+
+/// 等待握手应答的初始超时时间，每次重传握手packet后加倍
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 握手packet最多重传的次数，之后仍然没有应答时放弃握手
+pub const MAX_HANDSHAKE_RETRANSMITS: usize = 4;
+
+/// Retry令牌的有效期
+pub const RETRY_TOKEN_LIFETIME: Duration = Duration::from_secs(10);
+
+/// 对端地址验证之前，发送的数据量最多为接收数据量的倍数
+pub const AMPLIFICATION_FACTOR: u64 = 3;
+
+/// 超出抗放大限制而暂缓发送的packet，每隔一段时间检查一次能否发出
+pub const AMPLIFICATION_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// 路径MTU探测之前使用的datagram大小，所有IPv6路径以及绝大多数IPv4路径都能承载
+pub const INITIAL_MTU: usize = 1200;
+
+/// 二分查找的上下界之差小于该值时停止路径MTU探测
+pub const MTU_SEARCH_THRESHOLD: usize = 20;
+
+/// 同一大小的探测连续丢失多少次后认为路径无法承载该大小
+pub const MAX_MTU_PROBES: usize = 3;
+
+/// 对端地址验证之前暂缓路径MTU探测，每隔一段时间重新检查
+pub const MTU_PROBE_DELAY: Duration = Duration::from_millis(100);
@@ -1,15 +1,18 @@
 use super::{
-    bcast::{AckedBcast, ListenAckedBcast, ListenLostBcast, LostBcast},
-    ConnectionContext,
+    bcast::{
+        AckedBcast, ListenAckedBcast, ListenLostBcast, ListenProbeBcast, LostBcast, ProbeBcast,
+    },
+    constant::{K_MAX_PROBES, K_PACKET_THRESHOLD},
+    qlog, ConnectionContext,
 };
 use crate::{
     frame::ack::{AckFrame, AckSpans},
     packet::PacketMeta,
     types::PacketNum,
-    utils::task_guard::TaskGuard,
+    utils::range_set::RangeSet,
 };
 use actix::prelude::*;
-use std::collections::HashMap;
+use std::{collections::BTreeMap, time::Duration};
 use tokio::time::Instant;
 
 pub struct Inflight {
@@ -17,11 +20,38 @@ pub struct Inflight {
 
     acked_listeners: Vec<Recipient<AckedBcast>>,
     lost_listeners: Vec<Recipient<LostBcast>>,
+    probe_listeners: Vec<Recipient<ProbeBcast>>,
 
-    /// 当前正在传输的packet
+    /// 当前正在传输的ack eliciting packet，按packet number排序
+    packets: BTreeMap<PacketNum, PacketMeta>,
+
+    /// 目前为止对端确认过的最大packet number
+    largest_acked: Option<PacketNum>,
+
+    /// 对端确认过的所有packet，用于判断两个丢失的packet之间是否有packet被确认
+    ///
+    /// 早于最早的inflight packet的部分不再有用，会被及时清理
+    acked: RangeSet,
+
+    /// 对端报告过的最大CE计数，CE计数增加说明路径上发生了拥塞
+    ecn_ce_count: u64,
+
+    /// 第一次获得RTT样本的时间，只有在此之后发送的packet才会被用于判断持续拥塞
+    first_rtt_sample: Option<Instant>,
+
+    /// 基于时间阈值判断下一个packet丢失的时刻
     ///
-    /// 记录该packet的meta信息，以及超时任务的guard
-    packets: HashMap<PacketNum, (PacketMeta, TaskGuard)>,
+    /// 只有在存在早于`largest_acked`发送、但还没有被判定为丢失的packet时才有值
+    loss_time: Option<Instant>,
+
+    /// 最近一次发送ack eliciting packet的时间，PTO以此为基准计时
+    last_ack_eliciting_sent: Option<Instant>,
+
+    /// 连续发生PTO的次数，每次PTO到期后PTO时长翻倍，收到ack后清零
+    pto_count: u32,
+
+    /// 整个连接共用的丢包定时器，同时负责时间阈值丢包检测和PTO
+    timer_handle: Option<SpawnHandle>,
 }
 
 impl Actor for Inflight {
@@ -34,7 +64,154 @@ impl Inflight {
             ctx,
             acked_listeners: vec![],
             lost_listeners: vec![],
-            packets: HashMap::with_capacity(2048),
+            probe_listeners: vec![],
+            packets: BTreeMap::new(),
+            largest_acked: None,
+            acked: RangeSet::new(),
+            ecn_ce_count: 0,
+            first_rtt_sample: None,
+            loss_time: None,
+            last_ack_eliciting_sent: None,
+            pto_count: 0,
+            timer_handle: None,
+        }
+    }
+
+    /// 开始跟踪一个正在传输的packet
+    fn insert(&mut self, meta: PacketMeta) {
+        *self.ctx.bytes_in_flight.write().unwrap() += meta.bytes;
+        self.packets.insert(meta.packet_num, meta);
+    }
+
+    /// 停止跟踪一个已经被确认或判定丢失的packet
+    fn remove(&mut self, packet_num: PacketNum) -> Option<PacketMeta> {
+        let meta = self.packets.remove(&packet_num)?;
+        *self.ctx.bytes_in_flight.write().unwrap() -= meta.bytes;
+        Some(meta)
+    }
+
+    /// 基于RFC9002的包序阈值和时间阈值，找出所有已经丢失的packet并将其从inflight中移除
+    ///
+    /// 同时更新`loss_time`为下一个可能基于时间阈值判定丢包的时刻
+    fn detect_lost_packets(&mut self, now: Instant) -> Vec<PacketMeta> {
+        self.loss_time = None;
+
+        let Some(largest_acked) = self.largest_acked else {
+            return vec![];
+        };
+
+        let loss_delay = self.ctx.estimator.read().unwrap().loss_delay();
+
+        let mut lost = vec![];
+        for (&pn, meta) in self.packets.range(..largest_acked) {
+            let timeout = meta.sent + loss_delay;
+            if timeout <= now || largest_acked >= pn + K_PACKET_THRESHOLD {
+                lost.push(pn);
+            } else {
+                self.loss_time = Some(
+                    self.loss_time
+                        .map_or(timeout, |loss_time| loss_time.min(timeout)),
+                );
+            }
+        }
+
+        lost.into_iter().filter_map(|pn| self.remove(pn)).collect()
+    }
+
+    /// 当前的PTO时长，每次连续的PTO到期都会使其翻倍
+    fn pto(&self) -> Duration {
+        self.ctx.estimator.read().unwrap().rto() * 2u32.saturating_pow(self.pto_count)
+    }
+
+    /// PTO到期，发送探测包并对PTO进行指数退避
+    ///
+    /// 探测包不会将任何packet判定为丢失，丢包由后续ack触发的丢包检测来完成
+    fn on_pto(&mut self) {
+        self.pto_count += 1;
+
+        {
+            let mut stats = self.ctx.stats.write().unwrap();
+            stats.pto_count = self.pto_count;
+            stats.total_pto_count += 1;
+        }
+
+        tracing::debug!(parent: &self.ctx.span, pto_count = self.pto_count, "PTO expired");
+        let probes: Vec<_> = self.packets.values().take(K_MAX_PROBES).cloned().collect();
+        for listener in &self.probe_listeners {
+            listener.do_send(ProbeBcast(probes.clone()));
+        }
+    }
+
+    /// 重新部署丢包定时器
+    ///
+    /// 优先等待时间阈值到期；否则若还有正在传输的packet，则部署PTO
+    fn set_loss_timer(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.timer_handle.take() {
+            ctx.cancel_future(handle);
+        }
+
+        let deadline = match (self.loss_time, self.last_ack_eliciting_sent) {
+            (Some(loss_time), _) => loss_time,
+            (None, Some(sent)) if !self.packets.is_empty() => sent + self.pto(),
+            _ => return,
+        };
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.timer_handle = Some(ctx.notify_later(LossTimeout, timeout));
+    }
+
+    /// 基于RFC9002判断丢失的packet是否表明发生了持续拥塞
+    ///
+    /// 若存在两个丢失的packet，其发送时间间隔超过持续拥塞时长，且两者之间发送的packet都没有被确认，则认为发生了持续拥塞
+    fn in_persistent_congestion(&self, lost: &[PacketMeta]) -> bool {
+        let Some(first_rtt_sample) = self.first_rtt_sample else {
+            return false;
+        };
+
+        let duration = self
+            .ctx
+            .estimator
+            .read()
+            .unwrap()
+            .persistent_congestion_duration();
+
+        let mut start: Option<&PacketMeta> = None;
+        for meta in lost.iter().filter(|meta| meta.sent > first_rtt_sample) {
+            match start {
+                Some(start) if !self.acked.intersects(&(start.packet_num..meta.packet_num)) => {
+                    if meta.sent - start.sent > duration {
+                        return true;
+                    }
+                }
+                _ => start = Some(meta),
+            }
+        }
+
+        false
+    }
+
+    fn on_lost(&self, lost: Vec<PacketMeta>) {
+        if lost.is_empty() {
+            return;
+        }
+
+        for meta in &lost {
+            self.ctx.qlog(|| qlog::packet_lost(meta));
+        }
+        self.ctx.stats.write().unwrap().lost_packets += lost.len() as u64;
+        let persistent_congestion = self.in_persistent_congestion(&lost);
+        tracing::debug!(
+            parent: &self.ctx.span,
+            count = lost.len(),
+            first = lost[0].packet_num,
+            persistent_congestion,
+            "packets lost"
+        );
+        for listener in &self.lost_listeners {
+            listener.do_send(LostBcast {
+                packets: lost.clone(),
+                persistent_congestion,
+            });
         }
     }
 }
@@ -42,66 +219,125 @@ impl Inflight {
 impl Handler<Sent> for Inflight {
     type Result = ();
 
-    /// 有新packet发出时将其注册到inflight中，并部署一个超时任务
-    fn handle(&mut self, Sent(meta): Sent, _ctx: &mut Self::Context) -> Self::Result {
-        // 如果该packet不是ack eliciting的，则不需要部署超时任务
+    /// 有新packet发出时将其注册到inflight中，必要时部署丢包定时器
+    fn handle(&mut self, Sent(meta): Sent, ctx: &mut Self::Context) -> Self::Result {
+        // 如果该packet不是ack eliciting的，则不会收到对应的ack，也就不需要跟踪
         if !meta.is_ack_eliciting {
             return;
         }
 
-        let rto = self.ctx.estimator.read().unwrap().rto();
-        let listeners = self.lost_listeners.clone();
-        let timeout_meta = meta.clone();
-        // eprintln!("rto = {:?}", rto);
-        let guard = actix_rt::spawn(async move {
-            actix_rt::time::sleep(rto).await;
-
-            // eprintln!("{:?} lost, resending", timeout_meta.packet_num);
-
-            for listener in listeners {
-                listener.do_send(LostBcast(timeout_meta.clone()));
-            }
-        })
-        .into();
+        self.last_ack_eliciting_sent = Some(meta.sent);
+        self.insert(meta);
 
-        self.packets.insert(meta.packet_num, (meta, guard));
+        // PTO以最近一次发送的ack eliciting packet为基准，因此每次发送都需要重新部署
+        if self.loss_time.is_none() {
+            self.set_loss_timer(ctx);
+        }
     }
 }
 
 impl Handler<Ack> for Inflight {
     type Result = ();
 
-    /// 接收到ack frame时，将其对应的packet从inflight中移除，并取消超时任务；必要时更新RTT
-    fn handle(&mut self, Ack { frame, instant }: Ack, _ctx: &mut Self::Context) -> Self::Result {
-        self.packets.retain(|_, (_, guard)| !guard.is_finished());
-
+    /// 接收到ack frame时，将其对应的packet从inflight中移除并更新RTT，随后检测是否有packet丢失
+    fn handle(&mut self, Ack { frame, instant }: Ack, ctx: &mut Self::Context) -> Self::Result {
         let largest = frame.largest_ack;
-        let delay = frame.delay;
+        let delay = frame.delay(self.ctx.params.ack_delay_exponent);
+        let ecn = frame.ecn;
         let spans: AckSpans = frame.into();
 
-        for (pn, (_, guard)) in &self.packets {
-            if spans.contains(*pn) {
-                guard.abort();
-            }
+        if spans.iter().next().is_none() {
+            return;
+        }
+
+        self.largest_acked = Some(self.largest_acked.map_or(largest, |pn| pn.max(largest)));
+
+        // 收到ack说明对端仍然可达，重置PTO退避
+        self.pto_count = 0;
+        self.ctx.stats.write().unwrap().pto_count = 0;
+
+        // 根据ack frame来确认当前infight的packet中哪些已经被ack
+        let mut acked = vec![];
+        for range in spans.iter() {
+            let pns: Vec<_> = self
+                .packets
+                .range(range.clone())
+                .map(|(&pn, _)| pn)
+                .collect();
+            acked.extend(pns.into_iter().filter_map(|pn| self.remove(pn)));
+            self.acked.insert(range);
         }
 
         // 根据被确认的最大packet number的packet的发送时间来估算RTT
         // 只有最大packet number的packet是此次新ack时，才利用该packet来估算RTT
-        if let Some((PacketMeta { sent, .. }, _)) = self.packets.get(&largest) {
+        if let Some(PacketMeta { sent, .. }) = acked.iter().find(|meta| meta.packet_num == largest)
+        {
             let rtt = instant - *sent;
-            self.ctx.estimator.write().unwrap().update(delay, rtt);
+            self.ctx
+                .estimator
+                .write()
+                .unwrap()
+                .update(delay, rtt, instant);
+            self.first_rtt_sample.get_or_insert(instant);
+        }
+
+        // CE计数增加时视为一次拥塞事件，但并不认为有packet丢失
+        if let Some(ecn) = ecn.filter(|ecn| ecn.ce > self.ecn_ce_count) {
+            self.ecn_ce_count = ecn.ce;
+            self.ctx.stats.write().unwrap().ecn_ce_count = ecn.ce;
+
+            if let Some(PacketMeta { sent, .. }) =
+                acked.iter().find(|meta| meta.packet_num == largest)
+            {
+                self.ctx
+                    .congestion
+                    .write()
+                    .unwrap()
+                    .on_ecn_ce(Instant::now(), *sent);
+            }
+        }
+
+        // 对端确认了本端确实发出的packet，说明对端确实位于该地址
+        // 任意伪造的ack只能猜测packet number，不能据此解除抗放大限制
+        if !acked.is_empty() {
+            self.ctx.amplification.write().unwrap().validate();
+        }
+
+        let lost = self.detect_lost_packets(Instant::now());
+        self.on_lost(lost);
+
+        // 早于最早的inflight packet的确认信息已经不会再被用到
+        match self.packets.keys().next() {
+            Some(&first) => {
+                self.acked.remove(0..first);
+            }
+            None => self.acked = RangeSet::new(),
         }
 
-        // 根据ack frame来确认当前infight的packet中哪些已经被ack，并广播
-        let acked: Vec<_> = self
-            .packets
-            .extract_if(|&pn, _| spans.contains(pn))
-            .map(|(_, (meta, _))| meta)
-            .collect();
+        if !acked.is_empty() {
+            for listener in &self.acked_listeners {
+                listener.do_send(AckedBcast(acked.clone()));
+            }
+        }
+
+        self.set_loss_timer(ctx);
+    }
+}
 
-        for listener in &self.acked_listeners {
-            listener.do_send(AckedBcast(acked.clone()));
+impl Handler<LossTimeout> for Inflight {
+    type Result = ();
+
+    fn handle(&mut self, _: LossTimeout, ctx: &mut Self::Context) -> Self::Result {
+        self.timer_handle = None;
+
+        if self.loss_time.is_some() {
+            let lost = self.detect_lost_packets(Instant::now());
+            self.on_lost(lost);
+        } else {
+            self.on_pto();
         }
+
+        self.set_loss_timer(ctx);
     }
 }
 
@@ -121,6 +357,14 @@ impl Handler<ListenLostBcast> for Inflight {
     }
 }
 
+impl Handler<ListenProbeBcast> for Inflight {
+    type Result = ();
+
+    fn handle(&mut self, ListenProbeBcast(listener): ListenProbeBcast, _ctx: &mut Self::Context) {
+        self.probe_listeners.push(listener);
+    }
+}
+
 /// 收到新的ack frame
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -133,3 +377,229 @@ pub struct Ack {
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Sent(pub PacketMeta);
+
+/// 丢包定时器或PTO到期
+#[derive(Message)]
+#[rtype(result = "()")]
+struct LossTimeout;
+
+/// 测试中记录inflight发出的广播
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder {
+    lost: std::sync::Arc<std::sync::Mutex<Vec<PacketNum>>>,
+    probes: std::sync::Arc<std::sync::Mutex<Vec<Vec<PacketNum>>>>,
+}
+
+#[cfg(test)]
+impl Actor for Recorder {
+    type Context = Context<Self>;
+}
+
+#[cfg(test)]
+impl Handler<LostBcast> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, LostBcast { packets, .. }: LostBcast, _ctx: &mut Self::Context) {
+        let mut lost = self.lost.lock().unwrap();
+        lost.extend(packets.iter().map(|meta| meta.packet_num));
+    }
+}
+
+#[cfg(test)]
+impl Handler<ProbeBcast> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, ProbeBcast(probes): ProbeBcast, _ctx: &mut Self::Context) {
+        let probes = probes.iter().map(|meta| meta.packet_num).collect();
+        self.probes.lock().unwrap().push(probes);
+    }
+}
+
+#[cfg(test)]
+fn test_meta(packet_num: PacketNum, sent: Instant) -> PacketMeta {
+    PacketMeta {
+        packet_num,
+        frame_meta: vec![],
+        sent,
+        bytes: 1200,
+        is_ack_eliciting: true,
+        is_mtu_probe: false,
+    }
+}
+
+#[cfg(test)]
+fn test_ack(packet_num: PacketNum) -> Ack {
+    let mut spans = AckSpans::new();
+    spans.insert(packet_num);
+    Ack {
+        frame: spans.into(),
+        instant: Instant::now(),
+    }
+}
+
+/// 包序阈值立即判定丢包，其余packet等到时间阈值到期才判定丢失
+#[test]
+fn test_loss_detection() {
+    use super::ConnectionContext;
+
+    let mut inflight = Inflight::new(ConnectionContext::for_test());
+    let now = Instant::now();
+    for pn in 0..5 {
+        inflight.insert(test_meta(pn, now));
+    }
+    assert_eq!(*inflight.ctx.bytes_in_flight.read().unwrap(), 5 * 1200);
+    inflight.largest_acked = Some(5);
+
+    let lost = |lost: Vec<PacketMeta>| lost.iter().map(|meta| meta.packet_num).collect::<Vec<_>>();
+    // 与最大已确认包号相差至少`K_PACKET_THRESHOLD`的packet
+    assert_eq!(lost(inflight.detect_lost_packets(now)), [0, 1, 2]);
+
+    let loss_delay = inflight.ctx.estimator.read().unwrap().loss_delay();
+    assert_eq!(inflight.loss_time, Some(now + loss_delay));
+    let before = now + loss_delay - Duration::from_millis(1);
+    assert!(inflight.detect_lost_packets(before).is_empty());
+    assert_eq!(inflight.loss_time, Some(now + loss_delay));
+
+    assert_eq!(lost(inflight.detect_lost_packets(now + loss_delay)), [3, 4]);
+    assert_eq!(inflight.loss_time, None);
+    assert!(inflight.packets.is_empty());
+    assert_eq!(*inflight.ctx.bytes_in_flight.read().unwrap(), 0);
+}
+
+/// 两个丢失的packet间隔超过持续拥塞时长，且之间没有packet被确认时，才认为发生了持续拥塞
+#[test]
+fn test_persistent_congestion() {
+    use super::ConnectionContext;
+
+    let mut inflight = Inflight::new(ConnectionContext::for_test());
+    let duration = inflight
+        .ctx
+        .estimator
+        .read()
+        .unwrap()
+        .persistent_congestion_duration();
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+    let lost = [
+        test_meta(1, start + ms(1)),
+        test_meta(5, start + ms(2) + duration),
+    ];
+
+    // 还没有RTT样本
+    assert!(!inflight.in_persistent_congestion(&lost));
+
+    inflight.first_rtt_sample = Some(start);
+    assert!(inflight.in_persistent_congestion(&lost));
+    // 间隔不足持续拥塞时长
+    assert!(!inflight.in_persistent_congestion(&[lost[0].clone(), test_meta(5, start + duration)]));
+    // 早于第一个RTT样本发送的packet不参与判断
+    inflight.first_rtt_sample = Some(start + ms(1));
+    assert!(!inflight.in_persistent_congestion(&lost));
+
+    // 两者之间有packet被确认
+    inflight.first_rtt_sample = Some(start);
+    inflight.acked.insert(3..4);
+    assert!(!inflight.in_persistent_congestion(&lost));
+}
+
+/// 只有确认了inflight中的packet的ack才能验证对端地址
+#[test]
+fn test_ack_validates_address() {
+    use super::{amplification::AmplificationLimit, ConnectionContext};
+    use crate::testing::simulate;
+
+    simulate(0, |_| async {
+        let ctx = ConnectionContext::for_test();
+        *ctx.amplification.write().unwrap() = AmplificationLimit::unvalidated(400, 1200);
+        let inflight = Inflight::new(ctx.clone()).start();
+        inflight.do_send(Sent(test_meta(0, Instant::now())));
+
+        inflight.send(test_ack(1)).await.unwrap();
+        assert!(!ctx.amplification.read().unwrap().is_validated());
+
+        inflight.send(test_ack(0)).await.unwrap();
+        assert!(ctx.amplification.read().unwrap().is_validated());
+    });
+}
+
+/// 收到ack后剩余未达到阈值的packet由丢包定时器在`loss_time`判定丢失
+#[test]
+fn test_loss_timer() {
+    use super::ConnectionContext;
+    use crate::testing::simulate;
+    use tokio::time::sleep;
+
+    simulate(0, |_| async {
+        let inflight = Inflight::new(ConnectionContext::for_test()).start();
+        let recorder = Recorder::default();
+        let lost = recorder.lost.clone();
+        inflight.do_send(ListenLostBcast(recorder.start().recipient()));
+
+        let start = Instant::now();
+        for pn in 0..4 {
+            inflight.do_send(Sent(test_meta(pn, start)));
+        }
+
+        // RTT样本为50ms，1、2号packet在约56ms时达到时间阈值
+        sleep(Duration::from_millis(50)).await;
+        inflight.send(test_ack(3)).await.unwrap();
+        sleep(Duration::from_millis(1)).await;
+        assert_eq!(*lost.lock().unwrap(), [0]);
+
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(*lost.lock().unwrap(), [0, 1, 2]);
+    });
+}
+
+/// PTO到期时发送最早的若干packet作为探测，连续的PTO时长依次翻倍，收到新的ack后清零
+#[test]
+fn test_pto() {
+    use super::ConnectionContext;
+    use crate::testing::simulate;
+    use tokio::time::sleep_until;
+
+    simulate(0, |_| async {
+        let ctx = ConnectionContext::for_test();
+        let inflight = Inflight::new(ctx.clone()).start();
+        let recorder = Recorder::default();
+        let probes = recorder.probes.clone();
+        inflight.do_send(ListenProbeBcast(recorder.start().recipient()));
+
+        let start = Instant::now();
+        for pn in 0..4 {
+            inflight.do_send(Sent(test_meta(pn, start)));
+        }
+
+        let pto = ctx.estimator.read().unwrap().rto();
+        let ms = Duration::from_millis;
+        let pto_count = || {
+            let stats = ctx.stats.read().unwrap();
+            (stats.pto_count, stats.total_pto_count)
+        };
+
+        sleep_until(start + pto - ms(1)).await;
+        assert!(probes.lock().unwrap().is_empty());
+        sleep_until(start + pto + ms(1)).await;
+        assert_eq!(*probes.lock().unwrap(), [vec![0, 1]]);
+        assert_eq!(pto_count(), (1, 1));
+
+        // 第二次PTO的时长翻倍
+        sleep_until(start + 2 * pto - ms(1)).await;
+        assert_eq!(probes.lock().unwrap().len(), 1);
+        sleep_until(start + 2 * pto + ms(1)).await;
+        assert_eq!(probes.lock().unwrap().len(), 2);
+        assert_eq!(pto_count(), (2, 2));
+
+        sleep_until(start + 4 * pto - ms(1)).await;
+        assert_eq!(probes.lock().unwrap().len(), 2);
+        sleep_until(start + 4 * pto + ms(1)).await;
+        assert_eq!(probes.lock().unwrap().len(), 3);
+        assert_eq!(probes.lock().unwrap()[2].len(), K_MAX_PROBES);
+        assert_eq!(pto_count(), (3, 3));
+
+        // 新的ack重置退避，累计次数保持不变
+        inflight.send(test_ack(3)).await.unwrap();
+        assert_eq!(pto_count(), (0, 3));
+    });
+}
@@ -1,57 +1,127 @@
 use self::{
-    bcast::{ListenAckedBcast, ListenLostBcast},
+    amplification::AmplificationLimit,
+    bcast::{ListenAckedBcast, ListenLostBcast, ListenProbeBcast},
+    constant::{HANDSHAKE_TIMEOUT, INITIAL_MTU, MAX_HANDSHAKE_RETRANSMITS, RETRY_TOKEN_LIFETIME},
     packetizer::Packetizer,
+    qlog::{Event, Qlog, VantagePoint},
     stream::{RecvStream, SendStream},
     streams::Streams,
 };
 use crate::{
-    congestion::{rtt_estimator::RttEstimator, NewReno},
-    connection::{ack_sender::AckSender, inflight::Inflight, receiver::Receiver, sender::Sender},
-    packet::{CompressedPacket, HandshakePacket, LongPacket, MAX_PACKET_SIZE},
+    congestion::{
+        rtt_estimator::{RttEstimator, RttStats},
+        Controller, Pacer,
+    },
+    connection::{
+        ack_sender::AckSender,
+        inflight::Inflight,
+        mtu::MtuDiscovery,
+        receiver::{HandshakeReply, Receiver},
+        sender::Sender,
+    },
+    crypto::{
+        HandshakeAuth, Keypair, Keys, NoiseHandshake, TokenKey, NOISE_OVERHEAD, PUBLIC_KEY_LEN,
+        RANDOM_LEN, TAG_LEN,
+    },
+    packet::{
+        CompressedPacket, HandshakePacket, LongPacket, RetryPacket, COMPRESSED_PACKET_TYPE,
+        HANDSHAKE_PACKET_TYPE, MAX_PACKET_SIZE, MIN_HANDSHAKE_DATAGRAM_SIZE,
+    },
     serializable::Serializable,
+    socket::{self, AsyncDatagramSocket},
     types::ConnectionId,
 };
 use actix::prelude::*;
-use std::sync::{Arc, RwLock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
 use tokio::{
     io,
     net::{ToSocketAddrs, UdpSocket},
 };
+use tracing::Span;
 
+pub use config::TransportConfig;
+pub use stats::ConnectionStats;
 pub use transport::{CompressedParams, TransportParams};
 
 mod ack_sender;
+mod amplification;
 mod bcast;
+mod config;
 mod constant;
 mod inflight;
 mod listener;
+mod mtu;
 mod packetizer;
+mod qlog;
 mod receiver;
 mod sender;
+mod stats;
 mod stream;
 mod streams;
 mod transport;
 
 pub struct Connection {
-    id: ConnectionId,
+    ctx: ConnectionContext,
     addrs: Addrs,
     streams: Streams,
+    /// 使用Noise握手时对端的静态公钥
+    peer_key: Option<[u8; PUBLIC_KEY_LEN]>,
 }
 
 impl Connection {
     pub(crate) async fn with_socket(
-        socket: Arc<UdpSocket>,
+        socket: Arc<dyn AsyncDatagramSocket>,
+        local_params: TransportParams,
         params: TransportParams,
+        config: TransportConfig,
+        keys: Option<Keys>,
+        side: Side,
+        mut rng: StdRng,
     ) -> io::Result<Self> {
-        let id = rand::random();
+        let id = rng.gen();
+        let (vantage_point, amplification, handshake) = match side {
+            Side::Client => (VantagePoint::Client, AmplificationLimit::validated(), None),
+            Side::Server(amplification, handshake) => {
+                (VantagePoint::Server, amplification, Some(handshake))
+            }
+        };
+        let span = tracing::info_span!("connection", id, side = ?vantage_point);
+        let qlog = match &config.qlog_dir {
+            Some(dir) => Some(Arc::new(RwLock::new(Qlog::create(dir, id, vantage_point)?))),
+            None => None,
+        };
         let estimator = Arc::new(RwLock::new(RttEstimator::new(params.max_ack_delay)));
-        let congestion = Arc::new(RwLock::new(NewReno::default()));
+        let congestion = Arc::new(RwLock::new(config.congestion.build()));
+        let pacer = Arc::new(RwLock::new(Pacer::new(
+            config.max_send_rate,
+            tokio::time::Instant::now(),
+        )));
+        let stats = Arc::new(RwLock::new(ConnectionStats::default()));
+        let bytes_in_flight = Arc::new(RwLock::new(0));
+        // 接收缓冲区的大小限制了datagram的大小
+        let max_mtu = socket.max_datagram_size().min(MAX_PACKET_SIZE);
         let ctx = ConnectionContext {
             id,
             socket,
             estimator,
             congestion,
+            pacer,
+            stats,
+            bytes_in_flight,
+            local_params,
             params,
+            keys: keys.map(|keys| Arc::new(RwLock::new(keys))),
+            key_update_interval: config.key_update_interval,
+            min_datagram_size: config.min_datagram_size,
+            mtu: Arc::new(RwLock::new(INITIAL_MTU.min(max_mtu))),
+            amplification: Arc::new(RwLock::new(amplification)),
+            rng: Arc::new(RwLock::new(rng)),
+            qlog,
+            span,
         };
 
         let inflight = Inflight::new(ctx.clone()).start();
@@ -94,16 +164,35 @@ impl Connection {
                 streams: streams.inner().clone(),
             },
         )
+        .with_handshake(handshake)
+        .start();
+
+        let mtu_discovery = MtuDiscovery::new(
+            ctx.clone(),
+            mtu::Addrs {
+                packetizer: packetizer.clone(),
+            },
+            max_mtu,
+        )
         .start();
 
         inflight.do_send(ListenAckedBcast(sender.clone().recipient()));
         inflight.do_send(ListenAckedBcast(streams.inner().clone().recipient()));
         inflight.do_send(ListenLostBcast(sender.clone().recipient()));
         inflight.do_send(ListenLostBcast(streams.inner().clone().recipient()));
+        inflight.do_send(ListenAckedBcast(mtu_discovery.clone().recipient()));
+        inflight.do_send(ListenLostBcast(mtu_discovery.recipient()));
+        inflight.do_send(ListenProbeBcast(packetizer.clone().recipient()));
 
         let addrs = Addrs { receiver };
+        tracing::info!(parent: &ctx.span, mtu = *ctx.mtu.read().unwrap(), "handshake complete");
 
-        Ok(Self { id, addrs, streams })
+        Ok(Self {
+            ctx,
+            addrs,
+            streams,
+            peer_key: None,
+        })
     }
 
     pub async fn open(&mut self) -> SendStream {
@@ -115,22 +204,154 @@ impl Connection {
     }
 
     pub async fn close(self) {
+        let span = self.ctx.span.clone();
         self.streams.close().await;
-        println!("{} dropped", self.id);
+        tracing::info!(parent: &span, "connection closed");
     }
 
     pub fn id(&self) -> ConnectionId {
-        self.id
+        self.ctx.id
+    }
+
+    /// 使用Noise握手时对端的静态公钥，服务端可以据此判断客户端的身份
+    pub fn peer_key(&self) -> Option<[u8; PUBLIC_KEY_LEN]> {
+        self.peer_key
+    }
+
+    /// 获取连接当前的统计信息
+    pub fn stats(&self) -> ConnectionStats {
+        self.ctx.stats.read().unwrap().clone()
+    }
+
+    /// 获取连接当前的RTT统计信息
+    pub fn rtt_stats(&self) -> RttStats {
+        self.ctx.estimator.read().unwrap().stats()
+    }
+
+    /// 限制连接的发送速率，单位为字节每秒，`None`表示不限制
+    ///
+    /// 可以在传输过程中随时调整，立即生效
+    pub fn set_rate_limit(&self, max_send_rate: Option<u64>) {
+        self.ctx
+            .pacer
+            .write()
+            .unwrap()
+            .set_max_send_rate(max_send_rate);
+    }
+
+    /// 当前路径MTU探测确认的最大datagram大小
+    pub fn mtu(&self) -> usize {
+        *self.ctx.mtu.read().unwrap()
+    }
+
+    /// 当前的发送速率上限
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.ctx.pacer.read().unwrap().max_send_rate()
+    }
+
+    /// 本端当前使用的key phase，连接没有设置PSK时为`None`
+    pub fn key_phase(&self) -> Option<bool> {
+        self.ctx
+            .keys
+            .as_ref()
+            .map(|keys| keys.read().unwrap().key_phase())
+    }
+
+    /// 主动更新密钥，此后发出的packet使用下一代密钥保护
+    ///
+    /// 连接没有设置PSK，或上一次更新还没有被对端确认时返回错误
+    pub fn update_keys(&self) -> io::Result<()> {
+        let Some(keys) = &self.ctx.keys else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "connection is not encrypted",
+            ));
+        };
+
+        if keys.write().unwrap().update() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "previous key update is not confirmed",
+            ))
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct ConnectionContext {
     id: ConnectionId,
-    socket: Arc<UdpSocket>,
+    socket: Arc<dyn AsyncDatagramSocket>,
     estimator: Arc<RwLock<RttEstimator>>,
-    congestion: Arc<RwLock<NewReno>>,
+    congestion: Arc<RwLock<Box<dyn Controller>>>,
+    pacer: Arc<RwLock<Pacer>>,
+    stats: Arc<RwLock<ConnectionStats>>,
+    /// 正在传输的ack eliciting packet的总字节数，由inflight维护
+    bytes_in_flight: Arc<RwLock<u64>>,
+    /// 本端声明的传输参数
+    local_params: TransportParams,
+    /// 对端声明的传输参数
     params: TransportParams,
+    /// 设置了PSK时用于保护packet的密钥
+    keys: Option<Arc<RwLock<Keys>>>,
+    /// 自动更新密钥的间隔，见[`TransportConfig::key_update_interval`]
+    key_update_interval: Option<u64>,
+    /// 见[`TransportConfig::min_datagram_size`]
+    min_datagram_size: Option<usize>,
+    /// 对端地址验证之前的发送限制
+    amplification: Arc<RwLock<AmplificationLimit>>,
+    /// 当前路径能够承载的最大datagram大小，由路径MTU探测不断更新
+    mtu: Arc<RwLock<usize>>,
+    /// 连接中所有随机行为使用的随机数生成器，见[`TransportConfig::seed`]
+    rng: Arc<RwLock<StdRng>>,
+    /// 开启时记录qlog事件，见[`TransportConfig::qlog_dir`]
+    qlog: Option<Arc<RwLock<Qlog>>>,
+    /// 连接中所有事件的父span，以连接ID区分
+    span: Span,
+}
+
+impl ConnectionContext {
+    /// 开启qlog时记录一条事件，未开启时不会构造事件
+    fn qlog(&self, event: impl FnOnce() -> Event) {
+        if let Some(qlog) = &self.qlog {
+            qlog.write().unwrap().emit(event());
+        }
+    }
+}
+
+#[cfg(test)]
+impl ConnectionContext {
+    /// 单元测试中单独运行某个actor时使用的上下文，socket连接到没有对端的模拟网络
+    pub(crate) fn for_test() -> Self {
+        let params = TransportParams::default();
+        Self {
+            id: 0,
+            socket: Arc::new(crate::testing::SimNetwork::new(0).client()),
+            estimator: Arc::new(RwLock::new(RttEstimator::new(params.max_ack_delay))),
+            congestion: Arc::new(RwLock::new(TransportConfig::default().congestion.build())),
+            pacer: Arc::new(RwLock::new(Pacer::new(None, tokio::time::Instant::now()))),
+            stats: Arc::new(RwLock::new(ConnectionStats::default())),
+            bytes_in_flight: Arc::new(RwLock::new(0)),
+            local_params: params.clone(),
+            params,
+            keys: None,
+            key_update_interval: None,
+            min_datagram_size: None,
+            amplification: Arc::new(RwLock::new(AmplificationLimit::validated())),
+            mtu: Arc::new(RwLock::new(INITIAL_MTU)),
+            rng: Arc::new(RwLock::new(StdRng::seed_from_u64(0))),
+            qlog: None,
+            span: Span::none(),
+        }
+    }
+}
+
+/// 连接在握手中的角色
+pub(crate) enum Side {
+    Client,
+    /// 服务端在验证对端地址之前受抗放大限制，并且需要在握手应答丢失时重发应答
+    Server(AmplificationLimit, HandshakeReply),
 }
 
 struct Addrs {
@@ -138,17 +359,73 @@ struct Addrs {
 }
 
 pub struct ConnectionListener {
-    socket: Arc<UdpSocket>,
+    socket: Arc<dyn AsyncDatagramSocket>,
     params: Option<ListenParams>,
+    config: TransportConfig,
+    psk: Option<Vec<u8>>,
+    static_key: Option<Keypair>,
+    /// 开启地址验证时用于签发Retry令牌的密钥
+    retry: Option<TokenKey>,
+    /// 每个连接的随机数生成器都由它派生，使不同连接的ID和随机数各不相同
+    rng: Mutex<StdRng>,
 }
 
 impl ConnectionListener {
     pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
-        let socket = Arc::new(UdpSocket::bind(addr).await?);
-        Ok(Self {
-            socket,
+        let socket = UdpSocket::bind(addr).await?;
+        // 不支持ECN时只是失去了ECN带来的拥塞信号，不影响正常传输
+        let _ = socket::enable_ecn(&socket);
+        let _ = socket::enable_pmtu_probe(&socket);
+        Ok(Self::from_socket(socket))
+    }
+
+    /// 在已经准备好的传输上等待连接
+    pub fn from_socket(socket: impl AsyncDatagramSocket) -> Self {
+        let config = TransportConfig::default();
+        Self {
+            socket: Arc::new(socket),
             params: None,
-        })
+            rng: Mutex::new(config.rng()),
+            config,
+            psk: None,
+            static_key: None,
+            retry: None,
+        }
+    }
+
+    /// 是否在建立连接前验证客户端地址
+    ///
+    /// 开启后，首次握手只会收到一个携带令牌的Retry，客户端原样带回令牌后服务端才会创建连接，
+    /// 避免伪造源地址的握手packet消耗服务端资源
+    pub fn with_retry(mut self, enabled: bool) -> Self {
+        self.retry = enabled.then(|| TokenKey::generate(self.rng.get_mut().unwrap()));
+        self
+    }
+
+    /// 设置预共享密钥，只接受持有相同密钥的客户端，并对之后的所有packet进行加密和认证
+    ///
+    /// 压缩模式的应答不受保护
+    pub fn with_psk(mut self, psk: impl Into<Vec<u8>>) -> Self {
+        self.psk = Some(psk.into());
+        self
+    }
+
+    /// 设置服务端的静态密钥，使用Noise_IK握手认证服务端并协商之后所有packet的密钥
+    ///
+    /// 客户端需要预先持有对应的公钥，设置后不再使用PSK
+    pub fn with_static_key(mut self, keypair: Keypair) -> Self {
+        self.static_key = Some(keypair);
+        self
+    }
+
+    pub fn with_config(mut self, config: TransportConfig) -> Self {
+        self.rng = Mutex::new(config.rng());
+        self.config = config;
+        // 令牌密钥同样来自配置的随机数生成器，与调用顺序无关
+        if self.retry.is_some() {
+            self.retry = Some(TokenKey::generate(self.rng.get_mut().unwrap()));
+        }
+        self
     }
 
     pub fn with_transport_params(mut self, params: TransportParams) -> Self {
@@ -163,30 +440,130 @@ impl ConnectionListener {
 
     pub async fn accept(&self) -> io::Result<Option<Connection>> {
         let mut buf = [0u8; MAX_PACKET_SIZE];
+        let auth = self.psk.as_deref().map(HandshakeAuth::new);
+
+        // 未通过认证的握手packet直接丢弃，继续等待下一个
+        let (client, addr, noise, request) = loop {
+            let (n, addr) = self.socket.recv(&mut buf).await?;
+            // 未填充到最小长度的握手datagram直接丢弃，避免应答超过收到数据的数倍
+            if n < MIN_HANDSHAKE_DATAGRAM_SIZE {
+                tracing::trace!(%addr, len = n, "dropping undersized handshake datagram");
+                continue;
+            }
+            // 客户端只会发来握手packet，其他类型不必解码
+            if buf[0] != HANDSHAKE_PACKET_TYPE {
+                tracing::trace!(%addr, ty = buf[0], "dropping non-handshake datagram");
+                continue;
+            }
+
+            let (packet, noise) = match &self.static_key {
+                Some(static_key) => {
+                    // 读取失败后握手状态不再可用，每次都重新开始
+                    let mut noise = NoiseHandshake::responder(static_key)?;
+                    let packet = decode_noise_handshake(&buf[..n], &mut noise);
+                    (packet, Some(noise))
+                }
+                None => (decode_handshake(&buf[..n], auth.as_ref(), &[]), None),
+            };
+            let Some(LongPacket::Handshake(packet)) = packet else {
+                tracing::debug!(%addr, "dropping unauthenticated handshake packet");
+                continue;
+            };
+
+            if let Some(retry) = &self.retry {
+                let now = SystemTime::now();
+                // 地址尚未验证时只回复Retry，不分配任何连接状态
+                if !retry.verify(packet.token(), &addr, now, RETRY_TOKEN_LIFETIME) {
+                    let retry_random = self.rng.lock().unwrap().gen();
+                    let retry =
+                        LongPacket::Retry(RetryPacket::new(retry.sign(&addr, now), retry_random));
+                    // 设置了PSK或Noise时Retry同样需要认证，避免攻击者注入Retry
+                    let data = match noise {
+                        Some(mut noise) => encode_noise_handshake(retry, &mut noise)?,
+                        None => {
+                            let mut data = Vec::with_capacity(retry.len() + TAG_LEN);
+                            retry.encode(&mut data);
+                            if let Some(auth) = &auth {
+                                auth.sign(&retry_random, &packet.random(), &mut data);
+                            }
+                            data
+                        }
+                    };
+                    let _ = self.socket.send_to(&data, addr).await;
+                    tracing::debug!(%addr, "address not validated, sent retry");
+                    continue;
+                }
+            }
 
-        let (n, addr) = self.socket.recv_from(&mut buf).await?;
-        let client_params = match LongPacket::decode(&mut &buf[..n]) {
-            LongPacket::Handshake(packet) => packet.into_params(),
-            _ => panic!("unexpected packet"),
+            break (packet, addr, noise, buf[..n].to_vec());
         };
+        let client_random = client.random();
+        let client_params = client.into_params();
 
         self.socket.connect(addr).await?;
 
         match &self.params {
             Some(ListenParams::Transport(params)) => {
-                let packet = LongPacket::Handshake(HandshakePacket::new(params.clone()));
-                let len = packet.len();
-                packet.encode(&mut &mut buf[..]);
-                let _ = self.socket.send(&buf[..len]).await?;
+                let mut rng =
+                    StdRng::from_rng(&mut *self.rng.lock().unwrap()).expect("StdRng never fails");
+                let packet = HandshakePacket::new(params.clone(), rng.gen());
+                let server_random = packet.random();
 
-                let conn = Connection::with_socket(self.socket.clone(), client_params).await?;
+                let (data, keys, peer_key) = match noise {
+                    Some(mut noise) => {
+                        let data =
+                            encode_noise_handshake(LongPacket::Handshake(packet), &mut noise)?;
+                        let peer_key = noise.remote_key();
+                        (data, Some(noise.into_keys()?), peer_key)
+                    }
+                    None => {
+                        let packet = LongPacket::Handshake(packet);
+                        let mut data = Vec::with_capacity(packet.len() + TAG_LEN);
+                        packet.encode(&mut data);
+                        // 应答同时认证客户端的随机数，避免被重放给其他的握手
+                        if let Some(auth) = &auth {
+                            auth.sign(&server_random, &client_random, &mut data);
+                        }
+
+                        let keys = self
+                            .psk
+                            .as_deref()
+                            .map(|psk| Keys::derive(psk, &client_random, &server_random, false));
+                        (data, keys, None)
+                    }
+                };
+                let _ = self.socket.send(&data).await?;
+
+                // 通过Retry令牌验证过的地址不受抗放大限制
+                let amplification = match self.retry {
+                    Some(_) => AmplificationLimit::validated(),
+                    None => {
+                        AmplificationLimit::unvalidated(request.len() as u64, data.len() as u64)
+                    }
+                };
+                let handshake = HandshakeReply {
+                    request,
+                    response: data,
+                };
+                let mut conn = Connection::with_socket(
+                    self.socket.clone(),
+                    params.clone(),
+                    client_params,
+                    self.config.clone(),
+                    keys,
+                    Side::Server(amplification, handshake),
+                    rng,
+                )
+                .await?;
+                conn.peer_key = peer_key;
                 Ok(Some(conn))
             }
             Some(ListenParams::Compress(params)) => {
                 let packet = LongPacket::Compressed(CompressedPacket::new(params.clone()));
-                let len = packet.len();
-                packet.encode(&mut &mut buf[..]);
-                let _ = self.socket.send(&buf[..len]).await?;
+                let mut data = Vec::with_capacity(packet.len());
+                packet.encode(&mut data);
+                let _ = self.socket.send(&data).await?;
+                tracing::info!(%addr, "answered handshake with compressed params");
 
                 Ok(None)
             }
@@ -205,8 +582,12 @@ pub enum ListenParams {
 }
 
 pub struct ConnectionBuilder {
-    socket: Arc<UdpSocket>,
+    socket: Arc<dyn AsyncDatagramSocket>,
     params: TransportParams,
+    config: TransportConfig,
+    psk: Option<Vec<u8>>,
+    server_key: Option<[u8; PUBLIC_KEY_LEN]>,
+    static_key: Option<Keypair>,
 }
 
 impl ConnectionBuilder {
@@ -214,11 +595,24 @@ impl ConnectionBuilder {
         local: impl ToSocketAddrs,
         remote: impl ToSocketAddrs,
     ) -> io::Result<Self> {
-        let socket = Arc::new(UdpSocket::bind(local).await?);
+        let socket = UdpSocket::bind(local).await?;
         socket.connect(remote).await?;
+        let _ = socket::enable_ecn(&socket);
+        let _ = socket::enable_pmtu_probe(&socket);
 
-        let params = TransportParams::default();
-        Ok(Self { socket, params })
+        Ok(Self::from_socket(socket))
+    }
+
+    /// 在已经准备好的传输上建立连接，`socket`需要已经指向服务端
+    pub fn from_socket(socket: impl AsyncDatagramSocket) -> Self {
+        Self {
+            socket: Arc::new(socket),
+            params: TransportParams::default(),
+            config: TransportConfig::default(),
+            psk: None,
+            server_key: None,
+            static_key: None,
+        }
     }
 
     pub fn with_params(mut self, params: TransportParams) -> Self {
@@ -226,38 +620,240 @@ impl ConnectionBuilder {
         self
     }
 
-    pub async fn build(self) -> io::Result<ConnectionBuildResult> {
-        let mut buf = [0u8; MAX_PACKET_SIZE];
+    pub fn with_config(mut self, config: TransportConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// 设置预共享密钥，服务端需要持有相同的密钥，之后的所有packet都会被加密和认证
+    pub fn with_psk(mut self, psk: impl Into<Vec<u8>>) -> Self {
+        self.psk = Some(psk.into());
+        self
+    }
+
+    /// 固定服务端的静态公钥，使用Noise_IK握手认证服务端并协商之后所有packet的密钥
+    ///
+    /// 设置后不再使用PSK
+    pub fn with_server_key(mut self, server_key: [u8; PUBLIC_KEY_LEN]) -> Self {
+        self.server_key = Some(server_key);
+        self
+    }
+
+    /// 设置客户端的静态密钥，服务端可以通过[`Connection::peer_key`]识别客户端
+    ///
+    /// 未设置时每次握手随机生成
+    pub fn with_static_key(mut self, keypair: Keypair) -> Self {
+        self.static_key = Some(keypair);
+        self
+    }
 
-        let packet = LongPacket::Handshake(HandshakePacket::new(self.params));
-        let len = packet.len();
-        packet.encode(&mut &mut buf[..]);
-        let _ = self.socket.send(&buf[..len]).await?;
+    pub async fn build(self) -> io::Result<ConnectionBuildResult> {
+        let auth = self.psk.as_deref().map(HandshakeAuth::new);
 
-        let n = self.socket.recv(&mut buf).await?;
-        let packet = LongPacket::decode(&mut &buf[..n]);
+        let mut rng = self.config.rng();
+        let (mut packet, mut client_random, mut noise) =
+            self.handshake(&[], auth.as_ref(), &mut rng).await?;
+        // 服务端要求验证地址时，携带令牌重新发起握手
+        if let LongPacket::Retry(retry) = packet {
+            tracing::debug!("server requested address validation, retrying handshake");
+            (packet, client_random, noise) = self
+                .handshake(&retry.into_token(), auth.as_ref(), &mut rng)
+                .await?;
+        }
 
         match packet {
             LongPacket::Handshake(packet) => {
+                let (keys, peer_key) = match noise {
+                    Some(noise) => {
+                        let peer_key = noise.remote_key();
+                        (Some(noise.into_keys()?), peer_key)
+                    }
+                    None => {
+                        let keys = self
+                            .psk
+                            .as_deref()
+                            .map(|psk| Keys::derive(psk, &client_random, &packet.random(), true));
+                        (keys, None)
+                    }
+                };
                 let params = packet.into_params();
-                let conn = Connection::with_socket(self.socket.clone(), params).await?;
+                let mut conn = Connection::with_socket(
+                    self.socket.clone(),
+                    self.params,
+                    params,
+                    self.config,
+                    keys,
+                    Side::Client,
+                    rng,
+                )
+                .await?;
+                conn.peer_key = peer_key;
 
-                Ok(ConnectionBuildResult::Connection(conn))
+                Ok(ConnectionBuildResult::Connection(Box::new(conn)))
             }
             LongPacket::Compressed(packet) => {
                 let params = packet.into_params();
+                tracing::info!(size = params.size, "received compressed params");
                 Ok(ConnectionBuildResult::Compressed(params))
             }
-            _ => panic!("unexpected packet"),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected handshake response",
+            )),
+        }
+    }
+}
+
+impl ConnectionBuilder {
+    /// 发送一次握手packet并等待应答，返回应答、本次握手的随机数以及Noise握手状态
+    ///
+    /// 超时没有收到应答时原样重传握手packet，超过重传次数后返回错误
+    async fn handshake(
+        &self,
+        token: &[u8],
+        auth: Option<&HandshakeAuth>,
+        rng: &mut StdRng,
+    ) -> io::Result<(LongPacket, [u8; RANDOM_LEN], Option<NoiseHandshake>)> {
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let mut noise = match &self.server_key {
+            Some(server_key) => {
+                let static_key = self.static_key.clone().unwrap_or_else(Keypair::generate);
+                Some(NoiseHandshake::initiator(&static_key, server_key)?)
+            }
+            None => None,
+        };
+
+        let mut packet =
+            HandshakePacket::new(self.params.clone(), rng.gen()).with_token(token.to_vec());
+        let client_random = packet.random();
+
+        // 握手datagram需要填充到最小长度，填充位于加密或认证的范围之内
+        let data = match &mut noise {
+            Some(noise) => {
+                // Noise消息的payload中还包含packet的类型
+                packet.pad_to(MIN_HANDSHAKE_DATAGRAM_SIZE - 2 - NOISE_OVERHEAD);
+                encode_noise_handshake(LongPacket::Handshake(packet), noise)?
+            }
+            None => {
+                let tag_len = if auth.is_some() { TAG_LEN } else { 0 };
+                packet.pad_to(MIN_HANDSHAKE_DATAGRAM_SIZE - 1 - tag_len);
+                let packet = LongPacket::Handshake(packet);
+                let mut data = Vec::with_capacity(packet.len() + TAG_LEN);
+                packet.encode(&mut data);
+                if let Some(auth) = auth {
+                    auth.sign(&client_random, &[], &mut data);
+                }
+                data
+            }
+        };
+
+        let mut timeout = HANDSHAKE_TIMEOUT;
+        for _ in 0..=MAX_HANDSHAKE_RETRANSMITS {
+            let _ = self.socket.send(&data).await?;
+            let response = self.recv_response(token, &client_random, auth, &mut noise, &mut buf);
+            match tokio::time::timeout(timeout, response).await {
+                Ok(packet) => return Ok((packet?, client_random, noise)),
+                Err(_) => {
+                    tracing::debug!(?timeout, "handshake response timed out");
+                    timeout *= 2;
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "handshake timed out",
+        ))
+    }
+
+    /// 等待本次握手的应答，未通过认证的应答直接丢弃
+    ///
+    /// 携带令牌时说明已经收到过Retry，重复的Retry被忽略，收到另一个Retry时返回错误
+    async fn recv_response(
+        &self,
+        token: &[u8],
+        client_random: &[u8; RANDOM_LEN],
+        auth: Option<&HandshakeAuth>,
+        noise: &mut Option<NoiseHandshake>,
+        buf: &mut [u8],
+    ) -> io::Result<LongPacket> {
+        loop {
+            let (n, _) = self.socket.recv(buf).await?;
+            let packet = match noise {
+                Some(noise) => decode_noise_handshake(&buf[..n], noise),
+                None => decode_handshake(&buf[..n], auth, client_random),
+            };
+            match packet {
+                Some(LongPacket::Retry(retry)) if !token.is_empty() => {
+                    if retry.token() != token {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "received a second retry",
+                        ));
+                    }
+                }
+                Some(packet) => return Ok(packet),
+                None => tracing::debug!("dropping unauthenticated handshake response"),
+            }
         }
     }
 }
 
 pub enum ConnectionBuildResult {
-    Connection(Connection),
+    Connection(Box<Connection>),
     Compressed(CompressedParams),
 }
 
+/// 解码握手阶段的packet，设置了PSK时还需要验证握手packet和Retry的认证标签，packet不完整或验证失败时返回`None`
+///
+/// `context`为需要一并认证的额外数据
+fn decode_handshake(
+    buf: &[u8],
+    auth: Option<&HandshakeAuth>,
+    context: &[u8],
+) -> Option<LongPacket> {
+    let Some(auth) = auth else {
+        return LongPacket::try_decode(&mut &buf[..]);
+    };
+
+    // 压缩模式的应答不携带认证标签
+    if buf.first() == Some(&COMPRESSED_PACKET_TYPE) {
+        return LongPacket::try_decode(&mut &buf[..]);
+    }
+
+    let len = buf.len().checked_sub(TAG_LEN)?;
+    let packet = LongPacket::try_decode(&mut &buf[..len])?;
+    let random = match &packet {
+        LongPacket::Handshake(handshake) => handshake.random(),
+        LongPacket::Retry(retry) => retry.random(),
+        _ => return None,
+    };
+    auth.verify(&random, context, buf).ok()?;
+    Some(packet)
+}
+
+/// 使用Noise握手时，握手packet和Retry作为Noise消息的payload加密传输
+fn encode_noise_handshake(packet: LongPacket, noise: &mut NoiseHandshake) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(packet.len());
+    packet.encode(&mut payload);
+
+    let mut data = vec![HANDSHAKE_PACKET_TYPE];
+    noise.write(&payload, &mut data)?;
+    Ok(data)
+}
+
+/// 解码Noise握手消息，消息未通过认证或内容不完整时返回`None`，`noise`仍然可以继续读取
+fn decode_noise_handshake(buf: &[u8], noise: &mut NoiseHandshake) -> Option<LongPacket> {
+    match buf.first() {
+        Some(&HANDSHAKE_PACKET_TYPE) => {
+            let payload = noise.read(&buf[1..]).ok()?;
+            LongPacket::try_decode(&mut &payload[..])
+        }
+        // 压缩模式的应答不受保护
+        Some(&COMPRESSED_PACKET_TYPE) => LongPacket::try_decode(&mut &buf[..]),
+        _ => None,
+    }
+}
+
 // pub struct ConnectionListener {
 //     socket: Arc<UdpSocket>,
 //     params: TransportParams,
@@ -328,3 +924,188 @@ pub enum ConnectionBuildResult {
 //         Connection::with_socket(self.socket, params).await
 //     }
 // }
+
+/// 监听端丢弃伪造的datagram后仍然可以正常完成握手
+#[test]
+fn test_accept_malformed() {
+    use crate::{packet::RETRY_PACKET_TYPE, serializable::VarIntBufMut, testing::simulate};
+
+    simulate(3, |sim| async move {
+        let network = sim.network();
+        let listener = ConnectionListener::from_socket(network.server())
+            .with_psk("sim")
+            .with_config(sim.server_config())
+            .with_transport_params(TransportParams::default());
+        let server = actix_rt::spawn(async move { listener.accept().await.unwrap().unwrap() });
+
+        let socket = network.client();
+        // 非握手类型、未知类型以及声明了超长令牌的握手packet
+        for ty in [RETRY_PACKET_TYPE, 0xff, HANDSHAKE_PACKET_TYPE] {
+            let mut data = vec![ty];
+            data.put_varint(u32::MAX as u64);
+            data.resize(MIN_HANDSHAKE_DATAGRAM_SIZE, 0);
+            socket.send(&data).await.unwrap();
+        }
+
+        let result = ConnectionBuilder::from_socket(socket)
+            .with_psk("sim")
+            .with_config(sim.client_config())
+            .build()
+            .await
+            .unwrap();
+        let ConnectionBuildResult::Connection(_conn) = result else {
+            panic!("unexpected compressed params");
+        };
+        let _server = server.await.unwrap();
+    });
+}
+
+/// 设置了PSK时，注入的Retry无法通过认证，不会干扰真正的地址验证
+#[test]
+fn test_forged_retry() {
+    use crate::testing::simulate;
+
+    simulate(5, |sim| async move {
+        let network = sim.network();
+        let listener = ConnectionListener::from_socket(network.server())
+            .with_psk("sim")
+            .with_retry(true)
+            .with_config(sim.server_config())
+            .with_transport_params(TransportParams::default());
+        let server = actix_rt::spawn(async move { listener.accept().await.unwrap().unwrap() });
+
+        // 在真正的应答之前到达客户端
+        let packet = LongPacket::Retry(RetryPacket::new(vec![1; 40], [2; RANDOM_LEN]));
+        let mut data = vec![];
+        packet.encode(&mut data);
+        network.server().send(&data).await.unwrap();
+
+        let result = ConnectionBuilder::from_socket(network.client())
+            .with_psk("sim")
+            .with_config(sim.client_config())
+            .build()
+            .await
+            .unwrap();
+        let ConnectionBuildResult::Connection(_conn) = result else {
+            panic!("unexpected compressed params");
+        };
+        let _server = server.await.unwrap();
+    });
+}
+
+/// 握手应答丢失时客户端重传握手packet，服务端重发应答；始终没有应答时握手超时失败
+#[test]
+fn test_handshake_retransmit() {
+    use crate::testing::{simulate, LinkConfig};
+    use std::time::Duration;
+
+    simulate(6, |sim| async move {
+        let network = sim.network();
+        let listener = ConnectionListener::from_socket(network.server())
+            .with_psk("sim")
+            .with_config(sim.server_config())
+            .with_transport_params(TransportParams::default());
+        let server = actix_rt::spawn(async move { listener.accept().await.unwrap().unwrap() });
+
+        // 服务端的第一个应答丢失
+        network.set_downlink(LinkConfig::default().with_loss(1.0));
+        let restore = async {
+            tokio::time::sleep(HANDSHAKE_TIMEOUT / 2).await;
+            network.set_downlink(LinkConfig::default());
+        };
+
+        let start = tokio::time::Instant::now();
+        let build = ConnectionBuilder::from_socket(network.client())
+            .with_psk("sim")
+            .with_config(sim.client_config())
+            .build();
+        let (result, ()) = tokio::join!(build, restore);
+        let result = result.unwrap();
+        let ConnectionBuildResult::Connection(_conn) = result else {
+            panic!("unexpected compressed params");
+        };
+        assert!(start.elapsed() >= HANDSHAKE_TIMEOUT);
+        let _server = server.await.unwrap();
+    });
+
+    simulate(6, |sim| async move {
+        let start = tokio::time::Instant::now();
+        let result = ConnectionBuilder::from_socket(sim.network().client())
+            .with_config(sim.client_config())
+            .build()
+            .await;
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::TimedOut);
+        let total = HANDSHAKE_TIMEOUT * ((1 << (MAX_HANDSHAKE_RETRANSMITS + 1)) - 1);
+        assert!(start.elapsed() >= total && start.elapsed() < total + Duration::from_secs(1));
+    });
+}
+
+/// 路径上的CE标记经ACK_ECN回到发送端后触发拥塞响应：每个RTT最多缩小一次拥塞窗口，且不判定任何丢包
+#[test]
+fn test_ecn_ce() {
+    use crate::testing::{simulate, LinkConfig};
+    use std::time::Duration;
+
+    simulate(8, |sim| async move {
+        let network = sim.network();
+        let listener = ConnectionListener::from_socket(network.server())
+            .with_config(sim.server_config())
+            .with_transport_params(TransportParams::default().with_streams(1));
+        let server = actix_rt::spawn(async move { listener.accept().await.unwrap().unwrap() });
+
+        let result = ConnectionBuilder::from_socket(network.client())
+            .with_config(sim.client_config())
+            .build()
+            .await
+            .unwrap();
+        let ConnectionBuildResult::Connection(mut conn) = result else {
+            panic!("unexpected compressed params");
+        };
+        let mut server = server.await.unwrap();
+
+        let latency = Duration::from_millis(20);
+        network.set_uplink(LinkConfig::default().with_latency(latency));
+        network.set_downlink(LinkConfig::default().with_latency(latency).with_ce(0.05));
+
+        let mut stream = server.open().await;
+        let sender = actix_rt::spawn(async move {
+            stream.send(&vec![7u8; 4 << 20]).await.unwrap();
+            stream.wrote();
+            stream
+        });
+
+        // 记录服务端拥塞窗口每次缩小的时刻
+        let congestion = server.ctx.congestion.clone();
+        let reductions = Arc::new(Mutex::new(vec![]));
+        let recorded = reductions.clone();
+        let sampler = actix_rt::spawn(async move {
+            let mut window = congestion.read().unwrap().window();
+            loop {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                let current = congestion.read().unwrap().window();
+                if current < window {
+                    recorded.lock().unwrap().push(tokio::time::Instant::now());
+                }
+                window = current;
+            }
+        });
+
+        let mut stream = conn.accept().await.unwrap();
+        let mut buf = vec![0u8; 65536];
+        while stream.recv(&mut buf).await.unwrap() > 0 {}
+        let _stream = sender.await.unwrap();
+        sampler.abort();
+
+        let stats = server.stats();
+        let reductions = reductions.lock().unwrap().clone();
+        assert_eq!(stats.ecn_ce_count, network.downlink_stats().ce_marked);
+        assert!(!reductions.is_empty());
+        assert!(stats.ecn_ce_count > reductions.len() as u64);
+        // 同一个RTT内的多个CE标记只引起一次拥塞响应
+        for pair in reductions.windows(2) {
+            assert!(pair[1] - pair[0] >= latency * 2);
+        }
+        assert_eq!(stats.lost_packets, 0);
+        assert_eq!(network.downlink_stats().lost, 0);
+    });
+}
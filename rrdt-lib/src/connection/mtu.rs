@@ -0,0 +1,174 @@
+use super::{
+    bcast::{AckedBcast, LostBcast},
+    constant::{MAX_MTU_PROBES, MTU_PROBE_DELAY, MTU_SEARCH_THRESHOLD},
+    packetizer::{self, Packetizer},
+    ConnectionContext,
+};
+use actix::prelude::*;
+
+/// 路径MTU探测（DPLPMTUD，见RFC8899），从安全的初始大小开始，以二分查找的方式发送填充后的探测packet
+///
+/// 探测packet被确认后提升连接使用的datagram大小，探测丢失不会引起拥塞控制的反应
+pub struct MtuDiscovery {
+    ctx: ConnectionContext,
+    addrs: Addrs,
+    search: MtuSearch,
+}
+
+impl MtuDiscovery {
+    pub fn new(ctx: ConnectionContext, addrs: Addrs, max_mtu: usize) -> Self {
+        let mtu = *ctx.mtu.read().unwrap();
+        Self {
+            ctx,
+            addrs,
+            search: MtuSearch::new(mtu, max_mtu),
+        }
+    }
+
+    fn probe(&mut self, ctx: &mut Context<Self>) {
+        // 对端地址验证之前，填充的探测packet会受到抗放大限制
+        if !self.ctx.amplification.read().unwrap().is_validated() {
+            ctx.notify_later(Probe, MTU_PROBE_DELAY);
+            return;
+        }
+
+        if let Some(size) = self.search.next_probe() {
+            self.addrs.packetizer.do_send(packetizer::ProbeMtu(size));
+        }
+    }
+}
+
+impl Actor for MtuDiscovery {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.probe(ctx);
+    }
+}
+
+impl Handler<Probe> for MtuDiscovery {
+    type Result = ();
+
+    fn handle(&mut self, _: Probe, ctx: &mut Self::Context) -> Self::Result {
+        self.probe(ctx);
+    }
+}
+
+impl Handler<AckedBcast> for MtuDiscovery {
+    type Result = ();
+
+    fn handle(&mut self, AckedBcast(meta): AckedBcast, ctx: &mut Self::Context) -> Self::Result {
+        if !meta.iter().any(|meta| meta.is_mtu_probe) {
+            return;
+        }
+
+        if let Some(mtu) = self.search.on_probe_acked() {
+            *self.ctx.mtu.write().unwrap() = mtu;
+            self.ctx
+                .congestion
+                .write()
+                .unwrap()
+                .on_mtu_update(mtu as u64);
+        }
+        self.probe(ctx);
+    }
+}
+
+impl Handler<LostBcast> for MtuDiscovery {
+    type Result = ();
+
+    fn handle(&mut self, LostBcast { packets, .. }: LostBcast, ctx: &mut Self::Context) {
+        if !packets.iter().any(|meta| meta.is_mtu_probe) {
+            return;
+        }
+
+        self.search.on_probe_lost();
+        self.probe(ctx);
+    }
+}
+
+/// 检查是否可以发送下一个探测packet
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Probe;
+
+pub struct Addrs {
+    pub packetizer: Addr<Packetizer>,
+}
+
+/// 路径MTU的二分查找，同一时间最多只有一个探测packet在传输
+#[derive(Debug)]
+struct MtuSearch {
+    /// 已经确认路径能够承载的大小
+    low: usize,
+    /// 路径可能承载的最大大小
+    high: usize,
+    /// 正在探测的大小
+    probing: Option<usize>,
+    /// 当前大小的探测已经连续丢失的次数
+    lost: usize,
+}
+
+impl MtuSearch {
+    fn new(mtu: usize, max_mtu: usize) -> Self {
+        Self {
+            low: mtu,
+            high: max_mtu.max(mtu),
+            probing: None,
+            lost: 0,
+        }
+    }
+
+    /// 下一个需要探测的大小，已有探测在传输或查找已经结束时返回`None`
+    fn next_probe(&mut self) -> Option<usize> {
+        if self.probing.is_some() || self.high - self.low < MTU_SEARCH_THRESHOLD {
+            return None;
+        }
+
+        let size = self.low + (self.high - self.low).div_ceil(2);
+        self.probing = Some(size);
+        Some(size)
+    }
+
+    /// 探测packet被确认，返回新的路径MTU
+    fn on_probe_acked(&mut self) -> Option<usize> {
+        let size = self.probing.take()?;
+        self.low = size;
+        self.lost = 0;
+        Some(size)
+    }
+
+    /// 探测packet丢失，连续丢失多次后缩小查找范围
+    fn on_probe_lost(&mut self) {
+        let Some(size) = self.probing.take() else {
+            return;
+        };
+
+        self.lost += 1;
+        if self.lost >= MAX_MTU_PROBES {
+            self.high = size - 1;
+            self.lost = 0;
+        }
+    }
+}
+
+#[test]
+fn test_mtu_search() {
+    // 模拟一条MTU为1500的路径
+    let path_mtu = 1500;
+    let mut search = MtuSearch::new(1200, 8192);
+    let mut probes = 0;
+
+    while let Some(size) = search.next_probe() {
+        assert!(search.next_probe().is_none());
+        probes += 1;
+        if size <= path_mtu {
+            assert_eq!(search.on_probe_acked(), Some(size));
+        } else {
+            search.on_probe_lost();
+        }
+    }
+
+    assert!(search.low <= path_mtu && path_mtu - search.low < MTU_SEARCH_THRESHOLD);
+    assert!(probes < 50);
+}
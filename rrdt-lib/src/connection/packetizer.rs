@@ -1,13 +1,11 @@
 use super::{
+    bcast::ProbeBcast,
     constant::MAX_PACKET_DELAY,
     sender::{self, Sender},
     ConnectionContext,
 };
 use crate::{
-    frame::{stream::StreamDataFrame, Frame},
-    packet::Packet,
-    serializable::Serializable,
-    types::PacketNum,
+    crypto::TAG_LEN, frame::Frame, packet::Packet, serializable::Serializable, types::PacketNum,
 };
 use actix::prelude::*;
 use std::cell::RefCell;
@@ -34,7 +32,16 @@ impl Packetizer {
     }
 
     fn remaining(&self) -> usize {
-        self.current.borrow().remaining()
+        let mtu = *self.ctx.mtu.read().unwrap();
+        self.current.borrow().remaining(mtu)
+    }
+
+    fn tag_len(&self) -> usize {
+        if self.ctx.keys.is_some() {
+            TAG_LEN
+        } else {
+            0
+        }
     }
 
     fn is_empty(&self) -> bool {
@@ -74,7 +81,11 @@ impl Packetizer {
         let next = Packet::new(self.packet_num);
         self.packet_num += 1;
 
-        let prev = self.current.replace(next);
+        let mut prev = self.current.replace(next);
+        // 填充后的datagram长度包含认证标签
+        if let Some(min_datagram_size) = self.ctx.min_datagram_size {
+            prev.pad_to(min_datagram_size.saturating_sub(self.tag_len()));
+        }
         self.addrs.sender.do_send(sender::SendPacket(prev));
 
         if let Some(handle) = self.timer_handle {
@@ -94,12 +105,11 @@ impl Handler<Send> for Packetizer {
         match frame {
             Frame::Stream(mut frame) => {
                 // stream frame过大时可以进行拆分
-                let min_len = StreamDataFrame::min_len();
                 loop {
                     let len = frame.len();
                     let remaining = self.remaining();
 
-                    if ((min_len + 1)..len).contains(&remaining) {
+                    if ((frame.header_len() + 1)..len).contains(&remaining) {
                         let splitted = frame.split_to(remaining);
 
                         self.insert(ctx, Frame::Stream(splitted));
@@ -123,6 +133,37 @@ impl Handler<Send> for Packetizer {
     }
 }
 
+impl Handler<ProbeBcast> for Packetizer {
+    type Result = ();
+
+    /// PTO到期时，为每个探测立即发送一个包含PING frame的packet，以尽快引发对端的ack
+    fn handle(&mut self, ProbeBcast(probes): ProbeBcast, ctx: &mut Self::Context) -> Self::Result {
+        for _ in 0..probes.len().max(1) {
+            self.insert(ctx, Frame::Ping);
+            self.send(ctx);
+        }
+    }
+}
+
+impl Handler<ProbeMtu> for Packetizer {
+    type Result = ();
+
+    /// 立即发送一个填充到指定大小的PING packet，用于探测路径MTU
+    fn handle(&mut self, ProbeMtu(size): ProbeMtu, ctx: &mut Self::Context) -> Self::Result {
+        // 先发出当前的packet，保证packet number按顺序发送
+        self.send(ctx);
+
+        let next = Packet::new(self.packet_num);
+        self.packet_num += 1;
+
+        let mut packet = self.current.replace(next);
+        packet.push(Frame::Ping);
+        packet.pad_to(size.saturating_sub(self.tag_len()));
+        packet.set_mtu_probe();
+        self.addrs.sender.do_send(sender::SendPacket(packet));
+    }
+}
+
 impl Handler<Timeout> for Packetizer {
     type Result = ();
 
@@ -139,6 +180,11 @@ pub struct Send(pub Frame);
 #[rtype(result = "()")]
 pub struct Timeout;
 
+/// 发送一个大小为指定datagram大小的路径MTU探测packet
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ProbeMtu(pub usize);
+
 pub struct Addrs {
     pub sender: Addr<Sender>,
 }
@@ -0,0 +1,320 @@
+use crate::{
+    frame::{ack::AckSpans, Frame},
+    packet::{Packet, PacketMeta},
+    types::{ConnectionId, StreamId},
+};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+use tokio::{io, time::Instant};
+
+/// JSON-SEQ中每条记录开头的分隔符
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// 以qlog（draft-ietf-quic-qlog，JSON-SEQ格式）记录连接内部的事件，可以直接在qvis中打开
+///
+/// 每条记录占一行，写完一行立即落盘，进程异常退出时也不会丢失已经记录的事件
+pub struct Qlog {
+    writer: LineWriter<File>,
+    /// 事件时间的参考点
+    start: Instant,
+}
+
+impl Qlog {
+    /// 在`dir`下创建以连接ID命名的qlog文件，并写入trace的头部
+    pub fn create(dir: &Path, id: ConnectionId, vantage_point: VantagePoint) -> io::Result<Self> {
+        let file = File::create(dir.join(format!("{}.sqlog", id)))?;
+        let mut qlog = Self {
+            writer: LineWriter::new(file),
+            start: Instant::now(),
+        };
+
+        let reference_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1000.0;
+        qlog.write(&json!({
+            "qlog_version": "0.3",
+            "qlog_format": "JSON-SEQ",
+            "title": "rrdt",
+            "trace": {
+                "vantage_point": { "type": vantage_point.as_str() },
+                "common_fields": {
+                    "group_id": id.to_string(),
+                    "protocol_type": ["RRDT"],
+                    "time_format": "relative",
+                    "reference_time": reference_time,
+                },
+            },
+        }));
+        Ok(qlog)
+    }
+
+    pub fn emit(&mut self, Event { name, data }: Event) {
+        let time = self.start.elapsed().as_secs_f64() * 1000.0;
+        self.write(&json!({ "time": time, "name": name, "data": data }));
+    }
+
+    /// 写入失败时只是缺少了调试信息，不影响连接本身
+    fn write(&mut self, record: &Value) {
+        let mut line = vec![RECORD_SEPARATOR];
+        line.extend_from_slice(record.to_string().as_bytes());
+        line.push(b'\n');
+        let _ = self.writer.write_all(&line);
+    }
+}
+
+/// 记录trace的一方
+#[derive(Debug, Clone, Copy)]
+pub enum VantagePoint {
+    Client,
+    Server,
+}
+
+impl VantagePoint {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VantagePoint::Client => "client",
+            VantagePoint::Server => "server",
+        }
+    }
+}
+
+/// 一条qlog事件
+pub struct Event {
+    name: &'static str,
+    data: Value,
+}
+
+pub fn packet_sent(packet: &Packet, len: usize) -> Event {
+    Event {
+        name: "transport:packet_sent",
+        data: packet_data(packet, len),
+    }
+}
+
+pub fn packet_received(packet: &Packet, len: usize) -> Event {
+    Event {
+        name: "transport:packet_received",
+        data: packet_data(packet, len),
+    }
+}
+
+pub fn packet_lost(meta: &PacketMeta) -> Event {
+    Event {
+        name: "recovery:packet_lost",
+        data: json!({
+            "header": { "packet_type": "1RTT", "packet_number": meta.packet_num },
+            "trigger": if meta.is_mtu_probe { "mtu_probe" } else { "loss_detection" },
+        }),
+    }
+}
+
+/// 拥塞控制和RTT估计的状态，未知的字段为`None`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub congestion_window: Option<u64>,
+    pub bytes_in_flight: Option<u64>,
+    pub smoothed_rtt: Option<f64>,
+    pub latest_rtt: Option<f64>,
+    pub min_rtt: Option<f64>,
+    pub rtt_variance: Option<f64>,
+    pub pacing_rate: Option<u64>,
+}
+
+pub fn metrics_updated(metrics: Metrics) -> Event {
+    let mut data = serde_json::Map::new();
+    let mut insert = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            data.insert(key.to_string(), value);
+        }
+    };
+    insert(
+        "congestion_window",
+        metrics.congestion_window.map(Value::from),
+    );
+    insert("bytes_in_flight", metrics.bytes_in_flight.map(Value::from));
+    insert("smoothed_rtt", metrics.smoothed_rtt.map(Value::from));
+    insert("latest_rtt", metrics.latest_rtt.map(Value::from));
+    insert("min_rtt", metrics.min_rtt.map(Value::from));
+    insert("rtt_variance", metrics.rtt_variance.map(Value::from));
+    // qlog中pacing_rate的单位为bit每秒
+    insert(
+        "pacing_rate",
+        metrics.pacing_rate.map(|rate| (rate * 8).into()),
+    );
+
+    Event {
+        name: "recovery:metrics_updated",
+        data: data.into(),
+    }
+}
+
+/// stream在发送或接收方向上进入了新的状态
+pub fn stream_state_updated(id: StreamId, side: &'static str, state: &'static str) -> Event {
+    Event {
+        name: "transport:stream_state_updated",
+        data: json!({
+            "stream_id": id,
+            "stream_type": "unidirectional",
+            "stream_side": side,
+            "new": state,
+        }),
+    }
+}
+
+/// stream的流量控制上限发生了变化，`owner`为`local`时表示本端放开了接收窗口
+pub fn flow_control_updated(id: StreamId, owner: &'static str, max_data: u64) -> Event {
+    Event {
+        name: "transport:flow_control_updated",
+        data: json!({
+            "stream_id": id,
+            "owner": owner,
+            "max_stream_data": max_data,
+        }),
+    }
+}
+
+fn packet_data(packet: &Packet, len: usize) -> Value {
+    let frames: Vec<_> = packet.frames().iter().map(frame_data).collect();
+    json!({
+        "header": { "packet_type": "1RTT", "packet_number": packet.packet_num() },
+        "raw": { "length": len },
+        "frames": frames,
+    })
+}
+
+fn frame_data(frame: &Frame) -> Value {
+    match frame {
+        Frame::Handshake(_) => json!({ "frame_type": "handshake" }),
+        Frame::Stream(frame) => json!({
+            "frame_type": "stream",
+            "stream_id": frame.id,
+            "offset": frame.offset,
+            "length": frame.data.len(),
+            "fin": frame.fin,
+        }),
+        Frame::Ack(frame) => {
+            let acked_ranges: Vec<_> = AckSpans::from(frame.clone())
+                .iter()
+                .map(|range| [range.start, range.end - 1])
+                .collect();
+            let mut data = json!({
+                "frame_type": "ack",
+                "acked_ranges": acked_ranges,
+            });
+            if let Some(ecn) = &frame.ecn {
+                data["ect0"] = ecn.ect0.into();
+                data["ect1"] = ecn.ect1.into();
+                data["ce"] = ecn.ce.into();
+            }
+            data
+        }
+        Frame::MaxStreamData(frame) => json!({
+            "frame_type": "max_stream_data",
+            "stream_id": frame.id,
+            "maximum": frame.max_data,
+        }),
+        Frame::Ping => json!({ "frame_type": "ping" }),
+        Frame::Padding(len) => json!({
+            "frame_type": "padding",
+            "payload_length": len,
+        }),
+    }
+}
+
+/// 开启qlog后，每条记录都是合法的JSON，并且记录了收发和丢失的packet、拥塞控制以及stream的状态变化
+#[test]
+fn test_qlog() {
+    use crate::{
+        testing::{simulate, LinkConfig},
+        ConnectionBuildResult, ConnectionBuilder, ConnectionListener, TransportParams,
+    };
+
+    let dir = std::env::temp_dir().join(format!("rrdt-qlog-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let config_dir = dir.clone();
+    let (client_id, server_id) = simulate(11, |sim| async move {
+        let network = sim.network();
+        let listener = ConnectionListener::from_socket(network.server())
+            .with_config(sim.server_config().with_qlog_dir(config_dir.clone()))
+            .with_transport_params(TransportParams::default().with_streams(1));
+        let server = actix_rt::spawn(async move {
+            let mut conn = listener.accept().await.unwrap().unwrap();
+            let mut stream = conn.open().await;
+            // 超过整个接收窗口，发送端必须等待接收端放开流量控制
+            stream.send(&vec![7u8; 9 << 20]).await.unwrap();
+            stream.wrote();
+            stream.close().await;
+            conn
+        });
+
+        let result = ConnectionBuilder::from_socket(network.client())
+            .with_config(sim.client_config().with_qlog_dir(config_dir))
+            .build()
+            .await
+            .unwrap();
+        let ConnectionBuildResult::Connection(mut conn) = result else {
+            panic!("unexpected compressed params");
+        };
+        // 握手完成后再加入丢包，使发送端记录丢包事件
+        network.set_uplink(LinkConfig::default().with_loss(0.02));
+        network.set_downlink(LinkConfig::default().with_loss(0.02));
+
+        let mut stream = conn.accept().await.unwrap();
+        let mut buf = vec![0u8; 65536];
+        while stream.recv(&mut buf).await.unwrap() > 0 {}
+        let server = server.await.unwrap();
+        (conn.id(), server.id())
+    });
+
+    let read = |id: ConnectionId, vantage_point: &str| {
+        let content = std::fs::read(dir.join(format!("{}.sqlog", id))).unwrap();
+        let records: Vec<Value> = content
+            .split(|&b| b == RECORD_SEPARATOR)
+            .skip(1)
+            .map(|record| serde_json::from_slice(record).unwrap())
+            .collect();
+        assert_eq!(records[0]["qlog_format"], "JSON-SEQ");
+        assert_eq!(records[0]["trace"]["vantage_point"]["type"], vantage_point);
+
+        // 拥塞控制和RTT估计的状态记录在同一条事件中
+        for record in &records[1..] {
+            if record["name"] == "recovery:metrics_updated" {
+                for key in ["congestion_window", "bytes_in_flight", "smoothed_rtt"] {
+                    assert!(record["data"].get(key).is_some(), "missing {}", key);
+                }
+            }
+        }
+        records[1..]
+            .iter()
+            .map(|record| record["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let client = read(client_id, "client");
+    let server = read(server_id, "server");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for name in [
+        "transport:packet_sent",
+        "transport:packet_received",
+        "recovery:metrics_updated",
+        "transport:stream_state_updated",
+        "transport:flow_control_updated",
+    ] {
+        assert!(client.iter().any(|n| n == name), "missing {}", name);
+    }
+    for name in [
+        "transport:packet_sent",
+        "recovery:packet_lost",
+        "recovery:metrics_updated",
+    ] {
+        assert!(server.iter().any(|n| n == name), "missing {}", name);
+    }
+}
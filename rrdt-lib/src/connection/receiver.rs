@@ -1,11 +1,12 @@
 use super::streams::{self, StreamsInner};
-use super::{ack_sender, ConnectionContext};
+use super::{ack_sender, qlog, ConnectionContext};
 use super::{ack_sender::AckSender, inflight::Inflight};
 use crate::connection::inflight;
 use crate::frame::StreamFrame;
-use crate::packet::MAX_PACKET_SIZE;
+use crate::packet::{unprotect_header, Header, MAX_PACKET_SIZE};
 use crate::serializable::Serializable;
-use crate::{frame::Frame, packet::Packet};
+use crate::socket::{self, EcnCodepoint};
+use crate::{frame::Frame, packet::Packet, types::PacketNum};
 use actix::prelude::*;
 use tokio::io;
 use tokio::time::Instant;
@@ -13,28 +14,87 @@ use tokio::time::Instant;
 pub struct Receiver {
     ctx: ConnectionContext,
     addrs: Addrs,
+
+    /// 目前为止收到的最大packet number，用于还原被截断的packet number
+    largest_received: Option<PacketNum>,
+
+    /// 服务端在收到对端的第一个packet之前保留握手的应答
+    handshake: Option<HandshakeReply>,
 }
 
 impl Receiver {
     pub fn new(ctx: ConnectionContext, addrs: Addrs) -> Self {
-        Self { ctx, addrs }
+        Self {
+            ctx,
+            addrs,
+            largest_received: None,
+            handshake: None,
+        }
+    }
+
+    pub fn with_handshake(self, handshake: Option<HandshakeReply>) -> Self {
+        Self { handshake, ..self }
+    }
+
+    /// 对端重传了握手packet，说明握手的应答丢失了，原样重发应答
+    fn resend_handshake(&self, ctx: &mut Context<Self>) {
+        let Some(handshake) = &self.handshake else {
+            return;
+        };
+        let len = handshake.response.len() as u64;
+        let mut amplification = self.ctx.amplification.write().unwrap();
+        if amplification
+            .allowance()
+            .is_some_and(|allowance| len > allowance)
+        {
+            return;
+        }
+        amplification.on_sent(len);
+        drop(amplification);
+
+        tracing::debug!(parent: &self.ctx.span, "handshake retransmitted, resending response");
+        let socket = self.ctx.socket.clone();
+        let response = handshake.response.clone();
+        ctx.spawn(
+            async move {
+                let _ = socket.send(&response).await;
+            }
+            .into_actor(self),
+        );
     }
 }
 
+/// 服务端收到的握手datagram以及发出的应答
+pub(crate) struct HandshakeReply {
+    pub request: Vec<u8>,
+    pub response: Vec<u8>,
+}
+
 impl Actor for Receiver {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
         let receiver = ctx.address();
         let socket = self.ctx.socket.clone();
+        let stats = self.ctx.stats.clone();
         // 将socket接收数据的循环部署到独立的线程中
         ctx.spawn(
             async move {
-                let mut packet_buf = [0u8; MAX_PACKET_SIZE];
+                let mut bufs = vec![vec![0u8; MAX_PACKET_SIZE]; socket::BATCH_SIZE];
                 loop {
-                    let n = socket.recv(&mut packet_buf).await;
-                    let packet = n.map(|n| Packet::decode(&mut &packet_buf[..n]));
-                    receiver.do_send(Recv(packet));
+                    match socket.recv_batch(&mut bufs).await {
+                        Ok(datagrams) => {
+                            let mut stats = stats.write().unwrap();
+                            stats.recv_batches += 1;
+                            stats.datagrams_received += datagrams.len() as u64;
+                            drop(stats);
+
+                            for (buf, (n, ecn)) in bufs.iter().zip(datagrams) {
+                                receiver.do_send(Recv(Ok((buf[..n].to_vec(), ecn))));
+                            }
+                        }
+                        Err(err) => receiver.do_send(Recv(Err(err))),
+                    }
                 }
             }
             .into_actor(self),
@@ -45,9 +105,73 @@ impl Actor for Receiver {
 impl Handler<Recv> for Receiver {
     type Result = ();
 
-    fn handle(&mut self, Recv(packet): Recv, ctx: &mut Self::Context) -> Self::Result {
-        if let Ok(packet) = packet {
+    fn handle(&mut self, Recv(datagram): Recv, ctx: &mut Self::Context) -> Self::Result {
+        if let Ok((mut buf, ecn)) = datagram {
+            self.ctx
+                .amplification
+                .write()
+                .unwrap()
+                .on_received(buf.len() as u64);
+
+            if self
+                .handshake
+                .as_ref()
+                .is_some_and(|handshake| handshake.request == buf)
+            {
+                self.resend_handshake(ctx);
+                return;
+            }
+
+            if buf.len() < Header::min_len() {
+                return;
+            }
+
+            if let Some(keys) = &self.ctx.keys {
+                if unprotect_header(&mut buf, keys.read().unwrap().remote_header()).is_none() {
+                    return;
+                }
+            }
+
+            // packet number需要先还原，才能用于解密
+            let Some(mut header) = Header::try_decode(&mut &buf[..]) else {
+                return;
+            };
+            header.reconstruct_packet_num(self.largest_received);
+            let header_len = header.len();
+
+            let len = match &self.ctx.keys {
+                Some(keys) => match keys.write().unwrap().open(
+                    header.packet_num(),
+                    header.key_phase(),
+                    &mut buf,
+                    header_len,
+                ) {
+                    Ok(len) => len,
+                    // 未通过认证的packet直接丢弃
+                    Err(_) => return,
+                },
+                None => buf.len(),
+            };
+
+            // 无法解码的packet直接丢弃，不能让对端构造的内容使连接崩溃
+            let Some(packet) = Packet::try_decode_frames(header, &mut &buf[header_len..len]) else {
+                return;
+            };
+            // 对端已经完成握手，不会再重传握手packet
+            self.handshake = None;
+            self.ctx.qlog(|| qlog::packet_received(&packet, buf.len()));
             let packet_num = packet.packet_num();
+            self.largest_received = Some(
+                self.largest_received
+                    .map_or(packet_num, |largest| largest.max(packet_num)),
+            );
+
+            // 对端能够解密本端的packet，说明对端确实位于该地址
+            // 没有密钥时，要等ack确认了本端确实发出的packet才能验证，见`Inflight`
+            if self.ctx.keys.is_some() {
+                self.ctx.amplification.write().unwrap().validate();
+            }
+
             let is_ack_eliciting = packet.is_ack_eliciting();
             let instant = Instant::now();
 
@@ -79,7 +203,7 @@ impl Handler<Recv> for Receiver {
                                     .await
                                     .unwrap();
                             }
-                            Frame::Handshake(_) => {}
+                            Frame::Handshake(_) | Frame::Ping | Frame::Padding(_) => {}
                         }
                     }
                 }
@@ -90,6 +214,7 @@ impl Handler<Recv> for Receiver {
             self.addrs.ack_sender.do_send(ack_sender::Recv {
                 packet_num,
                 is_ack_eliciting,
+                ecn,
                 instant,
             });
         } else {
@@ -101,7 +226,7 @@ impl Handler<Recv> for Receiver {
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Recv(pub io::Result<Packet>);
+pub struct Recv(pub io::Result<(Vec<u8>, EcnCodepoint)>);
 
 #[derive(Clone)]
 pub struct Addrs {
@@ -1,21 +1,41 @@
 use super::{
     bcast::{AckedBcast, LostBcast},
+    constant::AMPLIFICATION_RETRY_DELAY,
     inflight::{self, Inflight},
+    qlog::{self, Metrics},
     ConnectionContext,
 };
 use crate::{
-    packet::{Packet, PacketMeta, MAX_PACKET_SIZE},
+    congestion::{rtt_estimator::RttEstimator, Controller},
+    crypto::TAG_LEN,
+    packet::{protect_header, Packet, PacketMeta},
     serializable::Serializable,
+    types::PacketNum,
 };
 use actix::prelude::*;
-use bytes::BytesMut;
+use std::{collections::VecDeque, time::Duration};
 use tokio::time::Instant;
 
 pub struct Sender {
     ctx: ConnectionContext,
     addrs: Addrs,
 
-    packet_buf: BytesMut,
+    /// 最近一次发送ack eliciting packet的时间，用于判断连接是否经历了空闲
+    last_ack_eliciting_sent: Option<Instant>,
+
+    /// 对端确认过的最大packet number，用于决定packet number的编码长度
+    largest_acked: Option<PacketNum>,
+
+    /// 等待合并发送的datagram
+    pending: Vec<Vec<u8>>,
+    /// 等待发送的packet的meta，发送之后注册到inflight中
+    pending_meta: Vec<PacketMeta>,
+    /// 是否有一批datagram正在发送
+    flushing: bool,
+    /// 超出抗放大限制而暂缓发送的datagram，按顺序等待限额增加后发出
+    ///
+    /// 这些packet还没有发出，不计入inflight，也不会交给拥塞控制
+    blocked: VecDeque<(Vec<u8>, PacketMeta, Option<qlog::Event>)>,
 }
 
 impl Sender {
@@ -23,9 +43,100 @@ impl Sender {
         Self {
             ctx,
             addrs,
-            packet_buf: BytesMut::with_capacity(MAX_PACKET_SIZE),
+            last_ack_eliciting_sent: None,
+            largest_acked: None,
+            pending: Vec::new(),
+            pending_meta: Vec::new(),
+            flushing: false,
+            blocked: VecDeque::new(),
+        }
+    }
+
+    /// 拥塞控制处理完ack或丢包之后的完整状态，此时RTT估计和inflight也都已经更新
+    fn metrics(&self, congestion: &dyn Controller, estimator: &RttEstimator) -> qlog::Event {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        qlog::metrics_updated(Metrics {
+            congestion_window: Some(congestion.window()),
+            bytes_in_flight: Some(*self.ctx.bytes_in_flight.read().unwrap()),
+            smoothed_rtt: Some(ms(estimator.rtt())),
+            latest_rtt: Some(ms(estimator.latest())),
+            min_rtt: Some(ms(estimator.windowed_min())),
+            rtt_variance: Some(ms(estimator.stats().var)),
+            pacing_rate: Some(congestion.pacing_rate(estimator)),
+        })
+    }
+
+    /// 按顺序发出限额允许的暂缓datagram，仍有datagram被阻塞时稍后再次检查
+    fn release(&mut self, ctx: &mut Context<Self>) {
+        while let Some((buf, ..)) = self.blocked.front() {
+            let len = buf.len() as u64;
+            if !self.ctx.amplification.write().unwrap().release(len) {
+                ctx.run_later(AMPLIFICATION_RETRY_DELAY, |act, ctx| act.release(ctx));
+                return;
+            }
+            let (buf, meta, event) = self.blocked.pop_front().unwrap();
+            self.push(buf, meta, event, ctx);
+        }
+    }
+
+    /// 将datagram加入下一批发送，此时packet才算真正发出
+    fn push(
+        &mut self,
+        buf: Vec<u8>,
+        mut meta: PacketMeta,
+        event: Option<qlog::Event>,
+        ctx: &mut Context<Self>,
+    ) {
+        self.ctx
+            .congestion
+            .write()
+            .unwrap()
+            .on_sent(meta.packet_num);
+        if let Some(event) = event {
+            self.ctx.qlog(|| event);
+        }
+        meta.sent = Instant::now();
+        self.pending.push(buf);
+        self.pending_meta.push(meta);
+
+        // 推迟到当前mailbox中的packet都处理完之后再发送，使它们能够合并为一批
+        if self.pending_meta.len() == 1 {
+            ctx.notify(Flush);
         }
     }
+
+    /// 将积攒的datagram合并为一批发送出去，同一时间只有一批datagram在发送
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.flushing || self.pending_meta.is_empty() {
+            return;
+        }
+        self.flushing = true;
+
+        let datagrams = std::mem::take(&mut self.pending);
+        let meta = std::mem::take(&mut self.pending_meta);
+        let socket = self.ctx.socket.clone();
+        let stats = self.ctx.stats.clone();
+        let inflight = self.addrs.inflight.clone();
+
+        ctx.spawn(
+            async move {
+                if !datagrams.is_empty() {
+                    let calls = socket.send_batch(&datagrams).await;
+                    let mut stats = stats.write().unwrap();
+                    stats.send_batches += calls as u64;
+                    stats.datagrams_sent += datagrams.len() as u64;
+                }
+                for meta in meta {
+                    inflight.do_send(inflight::Sent(meta));
+                }
+            }
+            .into_actor(self)
+            .map(|_, act, ctx| {
+                act.flushing = false;
+                act.flush(ctx);
+            }),
+        );
+    }
 }
 
 impl Actor for Sender {
@@ -35,21 +146,77 @@ impl Actor for Sender {
 impl Handler<SendPacket> for Sender {
     type Result = ();
 
-    fn handle(&mut self, SendPacket(packet): SendPacket, ctx: &mut Self::Context) -> Self::Result {
-        let size = packet.len();
-        let socket = self.ctx.socket.clone();
-        let mut buf = self.packet_buf.clone();
-        let inflight = self.addrs.inflight.clone();
+    fn handle(
+        &mut self,
+        SendPacket(mut packet): SendPacket,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if packet.is_ack_eliciting() {
+            let now = Instant::now();
+            // 所有数据都已被确认，且超过一个RTO没有发送过数据，说明连接经历了空闲，此前的拥塞窗口已经不能反映当前网络状况
+            //
+            // 仍有数据在传输时发送方只是在等待ack或PTO，不属于空闲
+            if let Some(last) = self.last_ack_eliciting_sent {
+                let rto = self.ctx.estimator.read().unwrap().rto();
+                let bytes_in_flight = *self.ctx.bytes_in_flight.read().unwrap();
+                if now - last > rto && bytes_in_flight == 0 {
+                    self.ctx.congestion.write().unwrap().on_idle_restart();
+                }
+            }
+            self.last_ack_eliciting_sent = Some(now);
+        }
 
-        ctx.spawn(
-            async move {
-                let meta = packet.meta(Instant::now());
-                packet.encode(&mut buf);
-                let _ = socket.send(&buf[..size]).await;
-                inflight.do_send(inflight::Sent(meta));
+        packet.truncate_packet_num(self.largest_acked);
+        let keys = self.ctx.keys.as_ref().map(|keys| {
+            let mut keys = keys.write().unwrap();
+            let (key_phase, key) = keys.seal_key(self.ctx.key_update_interval);
+            packet.set_key_phase(key_phase);
+            packet.reserve_sample();
+            (key, keys.local_header().clone())
+        });
+        let event = self.ctx.qlog.as_ref().map(|_| {
+            let tag_len = if keys.is_some() { TAG_LEN } else { 0 };
+            qlog::packet_sent(&packet, packet.len() + tag_len)
+        });
+
+        let header_len = packet.header_len();
+        let packet_num = packet.packet_num();
+        let mut buf = Vec::with_capacity(packet.len() + TAG_LEN);
+
+        let meta = packet.meta(Instant::now());
+        packet.encode(&mut buf);
+        if let Some((key, header_key)) = keys {
+            key.seal(packet_num, &mut buf, header_len);
+            protect_header(&mut buf, &header_key);
+        }
+
+        // 超出抗放大限制的packet暂缓发送，排在已经被阻塞的packet之后，保持发送顺序
+        let len = buf.len() as u64;
+        let mut amplification = self.ctx.amplification.write().unwrap();
+        let blocked = !self.blocked.is_empty()
+            || amplification
+                .allowance()
+                .is_some_and(|allowance| len > allowance);
+        if blocked {
+            amplification.on_queued(len);
+            drop(amplification);
+            self.blocked.push_back((buf, meta, event));
+            if self.blocked.len() == 1 {
+                ctx.run_later(AMPLIFICATION_RETRY_DELAY, |act, ctx| act.release(ctx));
             }
-            .into_actor(self),
-        );
+        } else {
+            amplification.on_sent(len);
+            drop(amplification);
+            self.push(buf, meta, event, ctx);
+        }
+    }
+}
+
+impl Handler<Flush> for Sender {
+    type Result = ();
+
+    fn handle(&mut self, _: Flush, ctx: &mut Self::Context) -> Self::Result {
+        self.flush(ctx);
     }
 }
 
@@ -57,9 +224,23 @@ impl Handler<AckedBcast> for Sender {
     type Result = ();
 
     fn handle(&mut self, AckedBcast(meta): AckedBcast, _ctx: &mut Self::Context) -> Self::Result {
-        for PacketMeta { sent, bytes, .. } in meta {
-            self.ctx.congestion.write().unwrap().on_ack(sent, bytes);
+        if let Some(largest) = meta.iter().map(|meta| meta.packet_num).max() {
+            self.largest_acked = Some(self.largest_acked.map_or(largest, |pn| pn.max(largest)));
         }
+
+        let estimator = self.ctx.estimator.read().unwrap();
+        let mut congestion = self.ctx.congestion.write().unwrap();
+        for PacketMeta {
+            packet_num,
+            sent,
+            bytes,
+            ..
+        } in meta
+        {
+            congestion.on_ack(packet_num, sent, bytes, &estimator);
+        }
+        self.ctx
+            .qlog(|| self.metrics(congestion.as_ref(), &estimator));
     }
 }
 
@@ -68,15 +249,29 @@ impl Handler<LostBcast> for Sender {
 
     fn handle(
         &mut self,
-        LostBcast(PacketMeta { sent, bytes, .. }): LostBcast,
+        LostBcast {
+            packets,
+            persistent_congestion,
+        }: LostBcast,
         _ctx: &mut Self::Context,
     ) -> Self::Result {
+        // 路径MTU探测packet的丢失说明的是packet过大，而不是网络发生了拥塞
+        let packets: Vec<_> = packets.iter().filter(|meta| !meta.is_mtu_probe).collect();
+        let Some(PacketMeta { sent, .. }) = packets.last() else {
+            return;
+        };
+        let bytes = packets.iter().map(|meta| meta.bytes).sum();
+
         let now = Instant::now();
+        let mut congestion = self.ctx.congestion.write().unwrap();
+        // 同一批丢失的packet只会引发一次拥塞事件，以其中最晚发送的packet为准
+        congestion.on_loss(now, *sent, bytes);
+        if persistent_congestion {
+            congestion.on_persistent_congestion();
+        }
+        let estimator = self.ctx.estimator.read().unwrap();
         self.ctx
-            .congestion
-            .write()
-            .unwrap()
-            .on_loss(now, sent, bytes);
+            .qlog(|| self.metrics(congestion.as_ref(), &estimator));
     }
 }
 
@@ -85,7 +280,121 @@ impl Handler<LostBcast> for Sender {
 #[rtype(result = "()")]
 pub struct SendPacket(pub Packet);
 
+/// 发送积攒的datagram
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Flush;
+
 pub struct Addrs {
     pub inflight: Addr<Inflight>,
     // pub congestion: Addr<Congestion>,
 }
+
+/// 只有在所有数据都被确认之后经历了空闲，才重置拥塞窗口；等待ack或PTO期间不属于空闲
+#[test]
+fn test_idle_restart() {
+    use crate::{frame::ack::AckSpans, frame::Frame, testing::simulate};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::time::sleep;
+
+    #[derive(Debug)]
+    struct IdleCounter(Arc<AtomicUsize>);
+
+    impl Controller for IdleCounter {
+        fn on_sent(&mut self, _packet_num: PacketNum) {}
+        fn on_ack(&mut self, _: PacketNum, _: Instant, _: u64, _: &RttEstimator) {}
+        fn on_loss(&mut self, _now: Instant, _sent: Instant, _bytes: u64) {}
+        fn on_ecn_ce(&mut self, _now: Instant, _sent: Instant) {}
+        fn on_persistent_congestion(&mut self) {}
+        fn on_idle_restart(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        fn on_mtu_update(&mut self, _mtu: u64) {}
+        fn window(&self) -> u64 {
+            u64::MAX
+        }
+    }
+
+    simulate(0, |_| async {
+        let ctx = ConnectionContext::for_test();
+        let restarts = Arc::new(AtomicUsize::new(0));
+        *ctx.congestion.write().unwrap() = Box::new(IdleCounter(restarts.clone()));
+
+        let inflight = Inflight::new(ctx.clone()).start();
+        let sender = Sender::new(
+            ctx.clone(),
+            Addrs {
+                inflight: inflight.clone(),
+            },
+        )
+        .start();
+        let send = |packet_num| {
+            let packet = Packet::new(packet_num).with_frames(vec![Frame::Ping]);
+            sender.send(SendPacket(packet))
+        };
+        let rto = || ctx.estimator.read().unwrap().rto();
+
+        send(0).await.unwrap();
+        // 超过一个RTO没有发送，但0号packet仍在等待ack
+        sleep(2 * rto()).await;
+        send(1).await.unwrap();
+        assert_eq!(restarts.load(Ordering::Relaxed), 0);
+
+        let mut spans = AckSpans::new();
+        spans.insert(0);
+        spans.insert(1);
+        sleep(Duration::from_millis(10)).await;
+        inflight
+            .send(inflight::Ack {
+                frame: spans.into(),
+                instant: Instant::now(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(*ctx.bytes_in_flight.read().unwrap(), 0);
+
+        sleep(2 * rto()).await;
+        send(2).await.unwrap();
+        assert_eq!(restarts.load(Ordering::Relaxed), 1);
+    });
+}
+
+/// 超出抗放大限制的packet暂缓发送，在限额增加之前不计入inflight
+#[test]
+fn test_amplification_blocked() {
+    use super::amplification::AmplificationLimit;
+    use crate::{frame::Frame, testing::simulate};
+    use tokio::time::sleep;
+
+    simulate(0, |_| async {
+        let ctx = ConnectionContext::for_test();
+        *ctx.amplification.write().unwrap() = AmplificationLimit::unvalidated(400, 0);
+
+        let inflight = Inflight::new(ctx.clone()).start();
+        let sender = Sender::new(
+            ctx.clone(),
+            Addrs {
+                inflight: inflight.clone(),
+            },
+        )
+        .start();
+        let packet = |packet_num| {
+            Packet::new(packet_num).with_frames(vec![Frame::Ping, Frame::Padding(900)])
+        };
+        let bytes_in_flight = || *ctx.bytes_in_flight.read().unwrap();
+
+        sender.send(SendPacket(packet(0))).await.unwrap();
+        sender.send(SendPacket(packet(1))).await.unwrap();
+        sleep(AMPLIFICATION_RETRY_DELAY * 3).await;
+        let first = bytes_in_flight();
+        assert!(first > 900);
+        assert_eq!(ctx.amplification.read().unwrap().allowance(), Some(0));
+
+        ctx.amplification.write().unwrap().on_received(400);
+        sleep(AMPLIFICATION_RETRY_DELAY * 2).await;
+        assert_eq!(bytes_in_flight(), first * 2);
+    });
+}
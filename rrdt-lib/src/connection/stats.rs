@@ -0,0 +1,41 @@
+/// 连接运行过程中的统计信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// 连续发生PTO的次数，收到ack后清零
+    ///
+    /// 每次PTO到期后，下一次的PTO时长都会翻倍
+    pub pto_count: u32,
+
+    /// 连接建立以来PTO到期的总次数
+    pub total_pto_count: u64,
+
+    /// 对端报告的被标记为CE的packet数量
+    pub ecn_ce_count: u64,
+
+    /// 被判定为丢失的packet总数
+    pub lost_packets: u64,
+
+    /// 发出的datagram总数
+    pub datagrams_sent: u64,
+
+    /// 发送datagram使用的系统调用次数
+    pub send_batches: u64,
+
+    /// 收到的datagram总数
+    pub datagrams_received: u64,
+
+    /// 接收datagram使用的系统调用次数
+    pub recv_batches: u64,
+}
+
+impl ConnectionStats {
+    /// 平均每次系统调用发出的datagram数量
+    pub fn avg_send_batch(&self) -> f64 {
+        self.datagrams_sent as f64 / self.send_batches.max(1) as f64
+    }
+
+    /// 平均每次系统调用收到的datagram数量
+    pub fn avg_recv_batch(&self) -> f64 {
+        self.datagrams_received as f64 / self.recv_batches.max(1) as f64
+    }
+}
@@ -1,9 +1,10 @@
 use self::{recv_stream::RecvStreamInner, send_stream::SendStreamInner};
-use super::packetizer::Packetizer;
+use super::{packetizer::Packetizer, ConnectionContext};
 use crate::types::StreamId;
 use actix::prelude::*;
 use bytes::Buf;
 use tokio::io;
+use tracing::Span;
 
 pub mod recv_stream;
 pub mod send_stream;
@@ -13,13 +14,15 @@ mod window;
 pub struct RecvStream {
     id: StreamId,
     inner: Addr<RecvStreamInner>,
+    span: Span,
 }
 
 impl RecvStream {
-    pub(crate) fn new(id: StreamId, addrs: Addrs) -> Self {
-        let inner = RecvStreamInner::new(id, addrs.clone()).start();
+    pub(crate) fn new(id: StreamId, ctx: ConnectionContext, addrs: Addrs) -> Self {
+        let span = tracing::info_span!(parent: &ctx.span, "recv_stream", id);
+        let inner = RecvStreamInner::new(id, ctx, span.clone(), addrs.clone()).start();
 
-        Self { id, inner }
+        Self { id, inner, span }
     }
 
     pub async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -43,7 +46,7 @@ impl RecvStream {
     pub async fn close(self) {
         let closing = self.inner.send(recv_stream::Close).await.unwrap();
         let _ = closing.await.unwrap();
-        eprintln!("recv stream {:?} closed", self.id);
+        tracing::debug!(parent: &self.span, "recv stream closed");
     }
 
     pub fn id(&self) -> StreamId {
@@ -59,13 +62,15 @@ impl RecvStream {
 pub struct SendStream {
     id: StreamId,
     inner: Addr<SendStreamInner>,
+    span: Span,
 }
 
 impl SendStream {
-    pub(crate) fn new(id: StreamId, addrs: Addrs) -> Self {
-        let inner = SendStreamInner::new(id, addrs.clone()).start();
+    pub(crate) fn new(id: StreamId, ctx: ConnectionContext, addrs: Addrs) -> Self {
+        let span = tracing::info_span!(parent: &ctx.span, "send_stream", id);
+        let inner = SendStreamInner::new(id, ctx, span.clone(), addrs.clone()).start();
 
-        Self { id, inner }
+        Self { id, inner, span }
     }
 
     pub async fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -85,7 +90,7 @@ impl SendStream {
     pub async fn close(self) {
         let closing = self.inner.send(send_stream::Close).await.unwrap();
         let _ = closing.await.unwrap();
-        eprintln!("send stream {:?} closed", self.id);
+        tracing::debug!(parent: &self.span, "send stream closed");
     }
 
     pub fn id(&self) -> StreamId {
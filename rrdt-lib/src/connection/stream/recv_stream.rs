@@ -1,6 +1,6 @@
 use super::window::{Chunk, RecvWindow};
 use crate::{
-    connection::{packetizer, sender::Sender},
+    connection::{packetizer, qlog, sender::Sender, ConnectionContext},
     frame::{stream::MaxStreamDataFrame, Frame},
     types::{Requester, Responder, StreamId},
 };
@@ -8,9 +8,12 @@ use actix::prelude::*;
 use bytes::Bytes;
 use std::collections::VecDeque;
 use tokio::{io, sync::oneshot};
+use tracing::Span;
 
 pub struct RecvStreamInner {
     id: StreamId,
+    ctx: ConnectionContext,
+    span: Span,
     addrs: super::Addrs,
 
     window: RecvWindow,
@@ -25,9 +28,11 @@ pub struct RecvStreamInner {
 }
 
 impl RecvStreamInner {
-    pub fn new(id: StreamId, addrs: super::Addrs) -> Self {
+    pub fn new(id: StreamId, ctx: ConnectionContext, span: Span, addrs: super::Addrs) -> Self {
         Self {
             id,
+            ctx,
+            span,
             addrs,
             window: RecvWindow::new(),
             pending: VecDeque::new(),
@@ -36,6 +41,14 @@ impl RecvStreamInner {
         }
     }
 
+    fn set_state(&mut self, state: State) {
+        if self.state != state {
+            self.ctx
+                .qlog(|| qlog::stream_state_updated(self.id, "receiving", state.name()));
+            self.state = state;
+        }
+    }
+
     fn close(&mut self) {
         if let Some(resp) = self.closing.take() {
             let _ = resp.send(());
@@ -75,7 +88,7 @@ impl RecvStreamInner {
 
                 if matches!(self.state, State::DataRecvd) && self.window.done() {
                     // 转移到最终状态时需尝试发送关闭通知
-                    self.state = State::DataRead;
+                    self.set_state(State::DataRead);
                     self.close();
                 }
             }
@@ -134,14 +147,14 @@ impl Handler<Write> for RecvStreamInner {
                 // 接收到带fin的`StreamDataFrame`后进入`SizeKnown`状态
                 State::Recv if fin => {
                     if self.window.recvd() {
-                        self.state = State::DataRecvd;
+                        self.set_state(State::DataRecvd);
                     } else {
-                        self.state = State::SizeKnown;
+                        self.set_state(State::SizeKnown);
                     }
                 }
                 // 进入到`SizeKnown`状态且已经收到了所有重传数据后进入`DataRecvd`状态
                 State::SizeKnown if self.window.recvd() => {
-                    self.state = State::DataRecvd;
+                    self.set_state(State::DataRecvd);
                 }
                 _ => {}
             }
@@ -164,17 +177,17 @@ impl Handler<Update> for RecvStreamInner {
     fn handle(&mut self, _: Update, _ctx: &mut Self::Context) -> Self::Result {
         let max_data = self.window.update();
 
-        match self.state {
-            // 只有在`Recv`状态才有必要向对端发送 `max_stream_data` frame
-            State::Recv => {
-                self.addrs
-                    .packetizer
-                    .do_send(packetizer::Send(Frame::MaxStreamData(MaxStreamDataFrame {
-                        id: self.id,
-                        max_data,
-                    })));
-            }
-            _ => {}
+        // 只有在`Recv`状态才有必要向对端发送 `max_stream_data` frame
+        if self.state == State::Recv {
+            tracing::debug!(parent: &self.span, max_data, "stream window updated");
+            self.ctx
+                .qlog(|| qlog::flow_control_updated(self.id, "local", max_data));
+            self.addrs
+                .packetizer
+                .do_send(packetizer::Send(Frame::MaxStreamData(MaxStreamDataFrame {
+                    id: self.id,
+                    max_data,
+                })));
         }
 
         max_data
@@ -241,7 +254,7 @@ pub struct Addrs {
     pub sender: Addr<Sender>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 enum State {
     Recv,
     SizeKnown,
@@ -250,3 +263,17 @@ enum State {
     ResetRecvd,
     ResetRead,
 }
+
+impl State {
+    /// qlog中对应的状态名
+    fn name(&self) -> &'static str {
+        match self {
+            State::Recv => "receive",
+            State::SizeKnown => "size_known",
+            State::DataRecvd => "data_received",
+            State::DataRead => "data_read",
+            State::ResetRecvd => "reset_received",
+            State::ResetRead => "reset_read",
+        }
+    }
+}
@@ -1,16 +1,19 @@
 use super::window::{Chunk, SendWindow};
 use crate::{
+    connection::{qlog, ConnectionContext},
     frame::stream::StreamDataFrame,
-    serializable::Serializable,
     types::{Requester, Responder, StreamId},
 };
 use actix::prelude::*;
 use bytes::Bytes;
 use std::ops::Range;
 use tokio::{io, sync::oneshot};
+use tracing::Span;
 
 pub struct SendStreamInner {
     id: StreamId,
+    ctx: ConnectionContext,
+    span: Span,
     addrs: super::Addrs,
 
     window: SendWindow,
@@ -25,9 +28,11 @@ pub struct SendStreamInner {
 }
 
 impl SendStreamInner {
-    pub fn new(id: StreamId, addrs: super::Addrs) -> Self {
+    pub fn new(id: StreamId, ctx: ConnectionContext, span: Span, addrs: super::Addrs) -> Self {
         Self {
             id,
+            ctx,
+            span,
             addrs,
             window: SendWindow::new(),
             state: State::Ready,
@@ -36,6 +41,14 @@ impl SendStreamInner {
         }
     }
 
+    fn set_state(&mut self, state: State) {
+        if self.state != state {
+            self.ctx
+                .qlog(|| qlog::stream_state_updated(self.id, "sending", state.name()));
+            self.state = state;
+        }
+    }
+
     fn close(&mut self) {
         if let Some(closing) = self.closing.take() {
             let _ = closing.send(());
@@ -51,16 +64,16 @@ impl Handler<Read> for SendStreamInner {
     type Result = io::Result<Option<StreamDataFrame>>;
 
     fn handle(&mut self, Read { bytes }: Read, _ctx: &mut Self::Context) -> Self::Result {
-        let data_len = bytes - StreamDataFrame::min_len();
+        let data_len = bytes.saturating_sub(StreamDataFrame::max_header_len());
 
         match self.state {
             State::Ready | State::Send => {
                 if let Some((Chunk(data, offset), fin)) = self.window.read(data_len)? {
                     if fin {
                         // 如果发送了fin frame则进入`DataSent`状态
-                        self.state = State::DataSent;
+                        self.set_state(State::DataSent);
                     } else {
-                        self.state = State::Send;
+                        self.set_state(State::Send);
                     }
 
                     Ok(Some(StreamDataFrame {
@@ -110,6 +123,9 @@ impl Handler<MaxData> for SendStreamInner {
     type Result = ();
 
     fn handle(&mut self, MaxData(max_data): MaxData, _ctx: &mut Self::Context) -> Self::Result {
+        tracing::trace!(parent: &self.span, max_data, "peer raised stream window");
+        self.ctx
+            .qlog(|| qlog::flow_control_updated(self.id, "remote", max_data));
         self.window.set_max_data(max_data)
     }
 }
@@ -122,7 +138,7 @@ impl Handler<Ack> for SendStreamInner {
 
         match self.state {
             State::DataSent if self.window.done() => {
-                self.state = State::DataRecvd;
+                self.set_state(State::DataRecvd);
                 self.close();
             }
             _ => {}
@@ -134,6 +150,7 @@ impl Handler<Retransmit> for SendStreamInner {
     type Result = ();
 
     fn handle(&mut self, Retransmit(range): Retransmit, _ctx: &mut Self::Context) -> Self::Result {
+        tracing::trace!(parent: &self.span, ?range, "retransmitting");
         self.window.retransmit(range);
     }
 }
@@ -215,7 +232,7 @@ pub struct Wrote;
 #[rtype(result = "Requester<()>")]
 pub struct Close;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 enum State {
     Ready,
     Send,
@@ -225,3 +242,17 @@ enum State {
     ResetSent,
     ResetRecvd,
 }
+
+impl State {
+    /// qlog中对应的状态名
+    fn name(&self) -> &'static str {
+        match self {
+            State::Ready => "ready",
+            State::Send => "send",
+            State::DataSent => "data_sent",
+            State::DataRecvd => "data_received",
+            State::ResetSent => "reset_sent",
+            State::ResetRecvd => "reset_received",
+        }
+    }
+}
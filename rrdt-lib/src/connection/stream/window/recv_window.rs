@@ -25,11 +25,18 @@ impl RecvWindow {
         }
     }
 
-    pub fn write(&mut self, Chunk(data, offset): Chunk, fin: bool) -> io::Result<usize> {
-        if data.len() == 0 || offset < self.consumed() {
+    pub fn write(&mut self, Chunk(mut data, mut offset): Chunk, fin: bool) -> io::Result<usize> {
+        let consumed = self.consumed();
+        if data.len() == 0 || offset + data.len() as u64 <= consumed {
             return Ok(0);
         }
 
+        // 重传的数据可能按照不同的大小拆分，只保留其中尚未被读取的部分
+        if offset < consumed {
+            let _ = data.split_to((consumed - offset) as usize);
+            offset = consumed;
+        }
+
         let right_offset = offset + data.len() as u64;
         let range = offset..right_offset;
 
@@ -103,3 +110,25 @@ impl RecvWindow {
         self.buf.start()
     }
 }
+
+/// 重传的chunk与已经读取的数据部分重叠时，只写入其中尚未读取的部分
+#[test]
+fn test_overlap_consumed() {
+    let mut window = RecvWindow::new();
+    let data: Vec<u8> = (0..20).collect();
+    let chunk = |range: std::ops::Range<usize>| {
+        Chunk(
+            Bytes::copy_from_slice(&data[range.clone()]),
+            range.start as u64,
+        )
+    };
+
+    assert_eq!(window.write(chunk(0..10), false).unwrap(), 10);
+    assert_eq!(window.read(10).unwrap().unwrap(), data[0..10]);
+
+    // 完全位于已读取部分之内的chunk直接忽略
+    assert_eq!(window.write(chunk(2..8), false).unwrap(), 0);
+    assert_eq!(window.write(chunk(5..20), true).unwrap(), 10);
+    assert_eq!(window.read(20).unwrap().unwrap(), data[10..20]);
+    assert!(window.done());
+}
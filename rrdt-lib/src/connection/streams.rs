@@ -11,17 +11,18 @@ use crate::types::{InfReceiver, InfSender, StreamId};
 use crate::utils::choice::Choice;
 use actix::prelude::*;
 use futures::future::join_all;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 
 pub struct StreamsInner {
     ctx: ConnectionContext,
     addrs: stream::Addrs,
     next_id: StreamId,
     // map: HashMap<StreamId, Stream>,
-    send_map: HashMap<StreamId, SendStream>,
-    recv_map: HashMap<StreamId, RecvStream>,
+    // 使用有序的map，使遍历顺序只由随机数生成器决定
+    send_map: BTreeMap<StreamId, SendStream>,
+    recv_map: BTreeMap<StreamId, RecvStream>,
 
     accept_handle: InfSender<RecvStream>,
 }
@@ -35,8 +36,8 @@ impl StreamsInner {
         Self {
             ctx,
             addrs,
-            send_map: HashMap::new(),
-            recv_map: HashMap::new(),
+            send_map: BTreeMap::new(),
+            recv_map: BTreeMap::new(),
             next_id: 0,
             accept_handle,
         }
@@ -45,13 +46,13 @@ impl StreamsInner {
     fn get_send(&mut self, id: StreamId) -> &SendStream {
         self.send_map
             .entry(id)
-            .or_insert_with(|| SendStream::new(id, self.addrs.clone()))
+            .or_insert_with(|| SendStream::new(id, self.ctx.clone(), self.addrs.clone()))
     }
 
     /// 远端打开的stream，会被放入`accept_queue`中
     fn get_recv(&mut self, id: StreamId) -> &RecvStream {
         self.recv_map.entry(id).or_insert_with(|| {
-            let stream = RecvStream::new(id, self.addrs.clone());
+            let stream = RecvStream::new(id, self.ctx.clone(), self.addrs.clone());
             let _ = self.accept_handle.send(stream.clone());
             stream
         })
@@ -64,15 +65,23 @@ impl Actor for StreamsInner {
     fn started(&mut self, ctx: &mut Self::Context) {
         let estimator = self.ctx.estimator.clone();
         let congestion = self.ctx.congestion.clone();
+        let pacer = self.ctx.pacer.clone();
+        let amplification = self.ctx.amplification.clone();
 
         ctx.run_interval(Duration::from_millis(1), move |_, ctx| {
-            let mut window = congestion.read().unwrap().window();
-            window += window / 4;
-
-            let rtt = estimator.read().unwrap().rtt().as_micros() as u64;
-            let bytes = (window * 1000 / rtt) as usize;
+            let rate = congestion
+                .read()
+                .unwrap()
+                .pacing_rate(&estimator.read().unwrap());
+            let mut bytes = pacer.write().unwrap().budget(rate, Instant::now());
+            // 对端地址验证之前，发送的数据量还受抗放大限制
+            if let Some(allowance) = amplification.read().unwrap().allowance() {
+                bytes = bytes.min(allowance as usize);
+            }
 
-            ctx.notify(Send { bytes });
+            if bytes > 0 {
+                ctx.notify(Send { bytes });
+            }
         });
     }
 }
@@ -81,16 +90,14 @@ impl Handler<AckedBcast> for StreamsInner {
     type Result = ();
 
     fn handle(&mut self, AckedBcast(meta): AckedBcast, _ctx: &mut Self::Context) -> Self::Result {
-        for PacketMeta { frame_meta, .. } in meta {
-            for meta in frame_meta {
-                match meta {
-                    // stream frame被ack时将send window中的对应部分标记为ack
-                    FrameMeta::Stream(StreamDataMeta { id, range }) => {
-                        let stream = self.get_send(id);
-                        stream.inner().do_send(send_stream::Ack(range));
-                    }
-                    _ => {}
+        for meta in frame_metas(meta) {
+            match meta {
+                // stream frame被ack时将send window中的对应部分标记为ack
+                FrameMeta::Stream(StreamDataMeta { id, range }) => {
+                    let stream = self.get_send(id);
+                    stream.inner().do_send(send_stream::Ack(range));
                 }
+                _ => {}
             }
         }
     }
@@ -99,12 +106,8 @@ impl Handler<AckedBcast> for StreamsInner {
 impl Handler<LostBcast> for StreamsInner {
     type Result = ();
 
-    fn handle(
-        &mut self,
-        LostBcast(PacketMeta { frame_meta, .. }): LostBcast,
-        _ctx: &mut Self::Context,
-    ) -> Self::Result {
-        for meta in frame_meta {
+    fn handle(&mut self, LostBcast { packets, .. }: LostBcast, _ctx: &mut Self::Context) {
+        for meta in frame_metas(packets) {
             match meta {
                 // stream frame丢失时将send window中的对应部分标记为retransmit
                 FrameMeta::Stream(StreamDataMeta { id, range }) => {
@@ -121,6 +124,11 @@ impl Handler<LostBcast> for StreamsInner {
     }
 }
 
+/// 依次取出一组packet中所有frame的元数据
+fn frame_metas(packets: Vec<PacketMeta>) -> impl Iterator<Item = FrameMeta> {
+    packets.into_iter().flat_map(|packet| packet.frame_meta)
+}
+
 impl Handler<Dispatch> for StreamsInner {
     type Result = ();
 
@@ -156,10 +164,13 @@ impl Handler<Send> for StreamsInner {
         }
 
         let streams: Vec<_> = self.send_map.values().cloned().collect();
+        let streams: Vec<_> = streams
+            .choice(&mut *self.ctx.rng.write().unwrap())
+            .collect();
         let packetizer = self.addrs.packetizer.clone();
         ctx.spawn(
             async move {
-                for stream in streams.choice() {
+                for stream in streams {
                     let frame = stream
                         .inner()
                         .send(send_stream::Read { bytes })
@@ -167,11 +178,11 @@ impl Handler<Send> for StreamsInner {
                         .unwrap();
 
                     if let Ok(Some(frame)) = frame {
-                        bytes -= frame.len();
+                        bytes = bytes.saturating_sub(frame.len());
                         let _ = packetizer.do_send(packetizer::Send(Frame::Stream(frame)));
                     }
 
-                    if bytes <= StreamDataFrame::min_len() {
+                    if bytes <= StreamDataFrame::max_header_len() {
                         break;
                     }
                 }
@@ -236,23 +247,26 @@ pub struct Close;
 
 /// 对外暴露的streams接口，用于获取对端开启的stream或主动开启新的stream
 pub struct Streams {
-    ctx: ConnectionContext,
     inner: Addr<StreamsInner>,
     accept_queue: InfReceiver<RecvStream>,
 
     recv_count: u16,
+
+    /// 对端承诺会开启的stream数量
+    max_recv_count: u16,
 }
 
 impl Streams {
     pub fn new(ctx: ConnectionContext, addrs: stream::Addrs) -> Self {
         let (accept_handle, accept_queue) = mpsc::unbounded_channel();
-        let inner = StreamsInner::new(ctx.clone(), addrs, accept_handle).start();
+        let max_recv_count = ctx.params.streams;
+        let inner = StreamsInner::new(ctx, addrs, accept_handle).start();
 
         Self {
-            ctx,
             inner,
             accept_queue,
             recv_count: 0,
+            max_recv_count,
         }
     }
 
@@ -264,7 +278,7 @@ impl Streams {
     /// 等待获取下一个对端开启的stream
     pub async fn accept(&mut self) -> Option<RecvStream> {
         // 开启数量达到了对端承诺的数量，则不再接受新的stream
-        if self.recv_count == self.ctx.params.streams {
+        if self.recv_count == self.max_recv_count {
             None
         } else {
             let stream = self.accept_queue.recv().await.unwrap();
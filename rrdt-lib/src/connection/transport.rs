@@ -1,9 +1,9 @@
 use bytes::{Buf, BufMut};
 
-use crate::serializable::Serializable;
+use crate::serializable::{varint_len, Serializable, VarIntBuf, VarIntBufMut};
 use std::time::Duration;
 
-use super::constant::DEFAULT_MAX_ACK_DELAY;
+use super::constant::{DEFAULT_ACK_DELAY_EXPONENT, DEFAULT_MAX_ACK_DELAY, MAX_ACK_DELAY_EXPONENT};
 
 /// 连接建立过程中双方声明的一些传输参数
 ///
@@ -13,6 +13,9 @@ pub struct TransportParams {
     /// 发送方承诺发送ack的最大延迟时间，单位毫秒
     pub max_ack_delay: Duration,
 
+    /// 发送方编码ack frame中的延迟时使用的指数，延迟以2^ack_delay_exponent微秒为单位
+    pub ack_delay_exponent: u8,
+
     /// initial value for the maximum amount of data that can be sent on the connection
     // pub initial_max_data: u64,
 
@@ -34,6 +37,11 @@ impl TransportParams {
         self
     }
 
+    pub fn with_ack_delay_exponent(mut self, ack_delay_exponent: u8) -> Self {
+        self.ack_delay_exponent = ack_delay_exponent.min(MAX_ACK_DELAY_EXPONENT);
+        self
+    }
+
     pub fn with_initial_max_stream_data(mut self, initial_max_stream_data: u64) -> Self {
         self.initial_max_stream_data = initial_max_stream_data;
         self
@@ -49,6 +57,7 @@ impl Default for TransportParams {
     fn default() -> Self {
         Self {
             max_ack_delay: DEFAULT_MAX_ACK_DELAY,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
             // initial_max_data: 1024 * 1024,
             initial_max_stream_data: 1024 * 1024,
             // initial_max_streams: 10,
@@ -57,32 +66,55 @@ impl Default for TransportParams {
     }
 }
 
-impl Serializable for TransportParams {
-    fn decode(data: &mut impl Buf) -> Self {
-        let max_ack_delay = data.get_u64();
-        let initial_max_stream_data = data.get_u64();
-        let streams = data.get_u16();
-
-        Self {
+impl TransportParams {
+    /// 解码对端声明的传输参数，数据不完整时返回`None`
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let max_ack_delay = data.try_get_varint()?;
+        let ack_delay_exponent = (data.try_get_varint()? as u8).min(MAX_ACK_DELAY_EXPONENT);
+        let initial_max_stream_data = data.try_get_varint()?;
+        let streams = data.try_get_varint()? as u16;
+
+        Some(Self {
             max_ack_delay: Duration::from_millis(max_ack_delay),
+            ack_delay_exponent,
             initial_max_stream_data,
             streams,
-        }
+        })
+    }
+}
+
+impl Serializable for TransportParams {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("truncated transport params")
     }
 
     fn encode(self, data: &mut impl BufMut) {
-        data.put_u64(self.max_ack_delay.as_millis() as u64);
-        data.put_u64(self.initial_max_stream_data);
-        data.put_u16(self.streams);
+        data.put_varint(self.max_ack_delay.as_millis() as u64);
+        data.put_varint(self.ack_delay_exponent as u64);
+        data.put_varint(self.initial_max_stream_data);
+        data.put_varint(self.streams as u64);
+    }
+
+    fn len(&self) -> usize {
+        // max_ack_delay
+        varint_len(self.max_ack_delay.as_millis() as u64) +
+            // ack_delay_exponent
+            varint_len(self.ack_delay_exponent as u64) +
+            // initial_max_stream_data
+            varint_len(self.initial_max_stream_data) +
+            // streams
+            varint_len(self.streams as u64)
     }
 
     fn min_len() -> usize {
         // max_ack_delay
-        std::mem::size_of::<u64>() +
+        1 +
+            // ack_delay_exponent
+            1 +
             // initial_max_stream_data
-            std::mem::size_of::<u64>() +
-            // initial_max_streams
-            std::mem::size_of::<u16>()
+            1 +
+            // streams
+            1
     }
 }
 
@@ -98,11 +130,21 @@ impl CompressedParams {
     }
 }
 
-impl Serializable for CompressedParams {
-    fn decode(data: &mut impl Buf) -> Self {
+impl CompressedParams {
+    /// 数据不完整时返回`None`
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        if data.remaining() < std::mem::size_of::<u8>() + std::mem::size_of::<u64>() {
+            return None;
+        }
         let byte = data.get_u8();
         let size = data.get_u64();
-        Self { byte, size }
+        Some(Self { byte, size })
+    }
+}
+
+impl Serializable for CompressedParams {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("truncated compressed params")
     }
 
     fn encode(self, data: &mut impl BufMut) {
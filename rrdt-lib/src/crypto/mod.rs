@@ -0,0 +1,428 @@
+use crate::types::PacketNum;
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use tokio::io;
+
+pub use noise::{Keypair, NoiseHandshake, NOISE_OVERHEAD, PUBLIC_KEY_LEN};
+pub use token::TokenKey;
+
+mod noise;
+mod token;
+
+/// AEAD认证标签的长度
+pub const TAG_LEN: usize = 16;
+
+/// 握手时双方各自生成的随机数的长度
+pub const RANDOM_LEN: usize = 32;
+
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const SECRET_LEN: usize = 32;
+
+/// 每一代密钥的secret，密钥、IV以及下一代secret都由它导出
+type Secret = [u8; SECRET_LEN];
+
+fn expand(secret: &Secret, label: &str, out: &mut [u8]) {
+    Hkdf::<Sha256>::from_prk(secret)
+        .expect("valid prk length")
+        .expand(label.as_bytes(), out)
+        .expect("valid output length");
+}
+
+/// 密钥更新时由当前secret导出下一代secret
+fn next_secret(secret: &Secret) -> Secret {
+    let mut next = [0u8; SECRET_LEN];
+    expand(secret, "rrdt ku", &mut next);
+    next
+}
+
+/// 用于保护packet的AEAD密钥，基于ChaCha20-Poly1305
+///
+/// nonce由IV与packet number异或得到，与QUIC相同，因此同一个密钥下packet number不能重复
+#[derive(Clone)]
+pub struct PacketKey {
+    cipher: ChaCha20Poly1305,
+    iv: [u8; IV_LEN],
+}
+
+impl PacketKey {
+    /// 由一代密钥的secret导出密钥与IV
+    fn from_secret(secret: &Secret) -> Self {
+        let mut key = [0u8; KEY_LEN];
+        let mut iv = [0u8; IV_LEN];
+        expand(secret, "rrdt key", &mut key);
+        expand(secret, "rrdt iv", &mut iv);
+
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            iv,
+        }
+    }
+
+    fn nonce(&self, packet_num: PacketNum) -> Nonce {
+        let mut nonce = self.iv;
+        for (byte, pn) in nonce[IV_LEN - 8..].iter_mut().zip(packet_num.to_be_bytes()) {
+            *byte ^= pn;
+        }
+        nonce.into()
+    }
+
+    /// 加密`buf[header_len..]`，以`buf[..header_len]`作为附加数据，并在末尾追加认证标签
+    pub fn seal(&self, packet_num: PacketNum, buf: &mut Vec<u8>, header_len: usize) {
+        let (header, payload) = buf.split_at_mut(header_len);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&self.nonce(packet_num), header, payload)
+            .expect("payload too large");
+        buf.extend_from_slice(&tag);
+    }
+
+    /// 解密并验证`buf[header_len..]`，返回去掉认证标签后的packet长度
+    pub fn open(
+        &self,
+        packet_num: PacketNum,
+        buf: &mut [u8],
+        header_len: usize,
+    ) -> io::Result<usize> {
+        if buf.len() < header_len + TAG_LEN {
+            return Err(auth_failed());
+        }
+
+        let len = buf.len() - TAG_LEN;
+        let (packet, tag) = buf.split_at_mut(len);
+        let (header, payload) = packet.split_at_mut(header_len);
+        self.cipher
+            .decrypt_in_place_detached(
+                &self.nonce(packet_num),
+                header,
+                payload,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| auth_failed())?;
+
+        Ok(len)
+    }
+}
+
+/// 基于ChaCha20的header保护密钥，见RFC9001 5.4.4节
+#[derive(Clone)]
+pub struct HeaderKey {
+    key: [u8; KEY_LEN],
+}
+
+impl HeaderKey {
+    fn from_secret(secret: &Secret) -> Self {
+        let mut key = [0u8; KEY_LEN];
+        expand(secret, "rrdt hp", &mut key);
+        Self { key }
+    }
+
+    /// 以密文中的采样生成5字节的掩码
+    ///
+    /// 采样的前4字节作为块计数器，其余12字节作为nonce
+    pub fn mask(&self, sample: &[u8; 16]) -> [u8; 5] {
+        let counter = u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+        let mut cipher = ChaCha20::new(&self.key.into(), sample[4..].into());
+        cipher.seek(counter as u64 * 64);
+
+        let mut mask = [0u8; 5];
+        cipher.apply_keystream(&mut mask);
+        mask
+    }
+}
+
+/// 一个连接的收发密钥，支持密钥更新，见RFC9001第6节
+///
+/// 每个方向各有一个secret，每次更新时由当前secret导出下一代secret，header保护密钥则始终不变。
+/// 当前使用的是哪一代密钥由short header中的key phase位表示
+pub struct Keys {
+    key_phase: bool,
+
+    local_secret: Secret,
+    /// 保护本端发出的packet
+    local: PacketKey,
+
+    remote_secret: Secret,
+    /// 验证并解密对端发来的packet
+    remote: PacketKey,
+    /// 对端的下一代密钥，预先导出以便在对端发起更新时验证packet
+    next_remote: PacketKey,
+    /// 对端的上一代密钥，用于解密更新之前发出、但乱序到达的packet
+    prev_remote: Option<PacketKey>,
+    /// 对端在当前key phase下发出的最小packet number，更早的packet使用上一代密钥
+    remote_phase_start: Option<PacketNum>,
+
+    /// 当前key phase是否已经被对端确认，确认之前不能再次更新
+    confirmed: bool,
+    /// 当前key phase下已经发出的packet数量
+    sent_in_phase: u64,
+
+    local_header: HeaderKey,
+    remote_header: HeaderKey,
+}
+
+impl Keys {
+    /// 由预共享密钥以及双方在握手中交换的随机数导出本次连接的密钥
+    ///
+    /// 随机数作为HKDF的salt，保证即使使用相同的PSK，每个连接的密钥也各不相同
+    pub fn derive(
+        psk: &[u8],
+        client_random: &[u8; RANDOM_LEN],
+        server_random: &[u8; RANDOM_LEN],
+        is_client: bool,
+    ) -> Self {
+        let salt = [&client_random[..], &server_random[..]].concat();
+        let (master, _) = Hkdf::<Sha256>::extract(Some(&salt), psk);
+        let master: Secret = master.into();
+
+        let mut client = [0u8; SECRET_LEN];
+        let mut server = [0u8; SECRET_LEN];
+        expand(&master, "rrdt client", &mut client);
+        expand(&master, "rrdt server", &mut server);
+
+        if is_client {
+            Self::new(client, server)
+        } else {
+            Self::new(server, client)
+        }
+    }
+
+    fn new(local_secret: Secret, remote_secret: Secret) -> Self {
+        Self {
+            key_phase: false,
+            local: PacketKey::from_secret(&local_secret),
+            local_header: HeaderKey::from_secret(&local_secret),
+            local_secret,
+            remote: PacketKey::from_secret(&remote_secret),
+            next_remote: PacketKey::from_secret(&next_secret(&remote_secret)),
+            prev_remote: None,
+            remote_phase_start: None,
+            remote_header: HeaderKey::from_secret(&remote_secret),
+            remote_secret,
+            confirmed: true,
+            sent_in_phase: 0,
+        }
+    }
+
+    pub fn key_phase(&self) -> bool {
+        self.key_phase
+    }
+
+    pub fn local_header(&self) -> &HeaderKey {
+        &self.local_header
+    }
+
+    pub fn remote_header(&self) -> &HeaderKey {
+        &self.remote_header
+    }
+
+    /// 是否可以发起新一次的密钥更新
+    pub fn can_update(&self) -> bool {
+        self.confirmed
+    }
+
+    /// 发起密钥更新，上一次更新还没有被对端确认时返回`false`
+    pub fn update(&mut self) -> bool {
+        if !self.can_update() {
+            return false;
+        }
+
+        self.next_phase();
+        self.confirmed = false;
+        self.remote_phase_start = None;
+        true
+    }
+
+    /// 切换到下一代密钥
+    fn next_phase(&mut self) {
+        self.key_phase = !self.key_phase;
+        self.sent_in_phase = 0;
+
+        self.local_secret = next_secret(&self.local_secret);
+        self.local = PacketKey::from_secret(&self.local_secret);
+
+        self.remote_secret = next_secret(&self.remote_secret);
+        let next_remote = PacketKey::from_secret(&next_secret(&self.remote_secret));
+        let remote = std::mem::replace(&mut self.next_remote, next_remote);
+        self.prev_remote = Some(std::mem::replace(&mut self.remote, remote));
+    }
+
+    /// 获取发送下一个packet时使用的key phase和密钥
+    ///
+    /// 当前key phase下发出的packet数量达到`update_interval`时，先自动更新密钥
+    pub fn seal_key(&mut self, update_interval: Option<u64>) -> (bool, PacketKey) {
+        if update_interval.is_some_and(|interval| self.sent_in_phase >= interval) {
+            self.update();
+        }
+
+        self.sent_in_phase += 1;
+        (self.key_phase, self.local.clone())
+    }
+
+    /// 根据packet的key phase选择对应的密钥解密并验证packet，返回去掉认证标签后的packet长度
+    ///
+    /// 若对端使用了下一代密钥且验证通过，说明对端发起了密钥更新，本端随之更新
+    pub fn open(
+        &mut self,
+        packet_num: PacketNum,
+        key_phase: bool,
+        buf: &mut [u8],
+        header_len: usize,
+    ) -> io::Result<usize> {
+        if key_phase == self.key_phase {
+            let len = self.remote.open(packet_num, buf, header_len)?;
+            // 收到对端使用新密钥的packet，说明本端发起的更新已被对端确认
+            self.confirmed = true;
+            self.remote_phase_start = Some(
+                self.remote_phase_start
+                    .map_or(packet_num, |start| start.min(packet_num)),
+            );
+            return Ok(len);
+        }
+
+        match &self.prev_remote {
+            Some(prev)
+                if self
+                    .remote_phase_start
+                    .is_none_or(|start| packet_num < start) =>
+            {
+                prev.open(packet_num, buf, header_len)
+            }
+            _ => {
+                let len = self.next_remote.open(packet_num, buf, header_len)?;
+                self.next_phase();
+                self.confirmed = true;
+                self.remote_phase_start = Some(packet_num);
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// 握手packet的认证，握手阶段还没有连接密钥，只能使用仅由PSK导出的密钥
+///
+/// 握手packet本身不加密，只在末尾附加认证标签，使得传输参数无法被篡改
+pub struct HandshakeAuth {
+    key: PacketKey,
+}
+
+impl HandshakeAuth {
+    pub fn new(psk: &[u8]) -> Self {
+        let (master, _) = Hkdf::<Sha256>::extract(None, psk);
+        let mut secret = [0u8; SECRET_LEN];
+        expand(&master.into(), "rrdt handshake", &mut secret);
+        Self {
+            key: PacketKey::from_secret(&secret),
+        }
+    }
+
+    /// 为`buf`中的握手packet附加认证标签，`context`为同时需要认证的额外数据
+    ///
+    /// `random`为该握手packet中携带的随机数，用于生成nonce
+    pub fn sign(&self, random: &[u8; RANDOM_LEN], context: &[u8], buf: &mut Vec<u8>) {
+        let tag = self
+            .key
+            .cipher
+            .encrypt_in_place_detached(
+                Nonce::from_slice(&random[..IV_LEN]),
+                &[context, &buf[..]].concat(),
+                &mut [],
+            )
+            .expect("empty payload");
+        buf.extend_from_slice(&tag);
+    }
+
+    /// 验证握手packet的认证标签，返回去掉认证标签后的长度
+    pub fn verify(
+        &self,
+        random: &[u8; RANDOM_LEN],
+        context: &[u8],
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        if buf.len() < TAG_LEN {
+            return Err(auth_failed());
+        }
+
+        let len = buf.len() - TAG_LEN;
+        self.key
+            .cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(&random[..IV_LEN]),
+                &[context, &buf[..len]].concat(),
+                &mut [],
+                Tag::from_slice(&buf[len..]),
+            )
+            .map_err(|_| auth_failed())?;
+
+        Ok(len)
+    }
+}
+
+fn auth_failed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "packet authentication failed")
+}
+
+#[test]
+fn test_seal_open() {
+    let client_random = [1u8; RANDOM_LEN];
+    let server_random = [2u8; RANDOM_LEN];
+    let mut client = Keys::derive(b"secret", &client_random, &server_random, true);
+    let mut server = Keys::derive(b"secret", &client_random, &server_random, false);
+
+    let (phase, key) = client.seal_key(None);
+    let mut buf = b"headerpayload".to_vec();
+    key.seal(7, &mut buf, 6);
+    assert_ne!(&buf[6..13], b"payload");
+
+    let mut received = buf.clone();
+    let len = server.open(7, phase, &mut received, 6).unwrap();
+    assert_eq!(&received[..len], b"headerpayload");
+
+    // packet number不同、数据被篡改或PSK不同时都无法通过认证
+    assert!(server.open(8, phase, &mut buf.clone(), 6).is_err());
+    let mut tampered = buf.clone();
+    tampered[0] ^= 1;
+    assert!(server.open(7, phase, &mut tampered, 6).is_err());
+    let mut other = Keys::derive(b"other", &client_random, &server_random, false);
+    assert!(other.open(7, phase, &mut buf.clone(), 6).is_err());
+}
+
+#[test]
+fn test_key_update() {
+    let mut client = Keys::derive(b"secret", &[1; RANDOM_LEN], &[2; RANDOM_LEN], true);
+    let mut server = Keys::derive(b"secret", &[1; RANDOM_LEN], &[2; RANDOM_LEN], false);
+
+    let send = |from: &mut Keys, to: &mut Keys, pn: PacketNum| {
+        let (phase, key) = from.seal_key(Some(3));
+        let mut buf = b"headerpayload".to_vec();
+        key.seal(pn, &mut buf, 6);
+        to.open(pn, phase, &mut buf, 6).map(|_| phase)
+    };
+
+    // 每个key phase发送3个packet后自动更新，对端识别到key phase翻转后随之更新
+    for pn in 0..3 {
+        assert!(!send(&mut client, &mut server, pn).unwrap());
+    }
+    assert!(send(&mut client, &mut server, 3).unwrap());
+    assert!(server.key_phase());
+    assert!(!client.can_update());
+
+    // 对端使用新密钥回复后，更新得到确认
+    assert!(send(&mut server, &mut client, 0).unwrap());
+    assert!(client.can_update());
+
+    // 更新前发出但乱序到达的packet仍然能够使用上一代密钥解密
+    let mut old = Keys::derive(b"secret", &[1; RANDOM_LEN], &[2; RANDOM_LEN], true);
+    let (phase, key) = old.seal_key(None);
+    let mut buf = b"headerpayload".to_vec();
+    key.seal(2, &mut buf, 6);
+    assert!(server.open(2, phase, &mut buf, 6).is_ok());
+}
@@ -0,0 +1,155 @@
+use super::{auth_failed, Keys, SECRET_LEN, TAG_LEN};
+use snow::{params::NoiseParams, Builder, HandshakeState};
+use tokio::io;
+
+/// 基于X25519的Noise_IK握手：客户端预先知道服务端的静态公钥，两条消息完成双向认证
+const NOISE_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_SHA256";
+
+/// 双方在握手开始前就已经确定的上下文，避免握手消息被用于其他协议
+const NOISE_PROLOGUE: &[u8] = b"rrdt";
+
+/// X25519公钥的长度
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Noise_IK中单条握手消息相对于payload的最大额外开销：临时公钥、加密的静态公钥及两个认证标签
+pub const NOISE_OVERHEAD: usize = 2 * PUBLIC_KEY_LEN + 2 * TAG_LEN;
+
+/// Noise握手使用的X25519静态密钥对
+///
+/// 服务端的公钥需要通过其他途径分发给客户端，私钥应当妥善保存
+#[derive(Clone)]
+pub struct Keypair {
+    pub private: [u8; 32],
+    pub public: [u8; PUBLIC_KEY_LEN],
+}
+
+impl Keypair {
+    /// 随机生成一个新的密钥对
+    pub fn generate() -> Self {
+        let keypair = Builder::new(params())
+            .generate_keypair()
+            .expect("supported noise params");
+
+        Self {
+            private: keypair.private.try_into().expect("x25519 private key"),
+            public: keypair.public.try_into().expect("x25519 public key"),
+        }
+    }
+}
+
+/// 握手双方各自持有的Noise握手状态
+///
+/// 握手packet中的内容作为Noise消息的payload被加密传输，握手结束后由Noise导出1-RTT的packet密钥
+pub struct NoiseHandshake {
+    state: HandshakeState,
+}
+
+impl NoiseHandshake {
+    /// 客户端发起握手，`server_key`为预先固定的服务端公钥
+    pub fn initiator(local: &Keypair, server_key: &[u8; PUBLIC_KEY_LEN]) -> io::Result<Self> {
+        let state = Builder::new(params())
+            .local_private_key(&local.private)
+            .remote_public_key(server_key)
+            .prologue(NOISE_PROLOGUE)
+            .build_initiator()
+            .map_err(|_| auth_failed())?;
+        Ok(Self { state })
+    }
+
+    pub fn responder(local: &Keypair) -> io::Result<Self> {
+        let state = Builder::new(params())
+            .local_private_key(&local.private)
+            .prologue(NOISE_PROLOGUE)
+            .build_responder()
+            .map_err(|_| auth_failed())?;
+        Ok(Self { state })
+    }
+
+    /// 将`payload`加密为下一条握手消息，追加到`buf`末尾
+    pub fn write(&mut self, payload: &[u8], buf: &mut Vec<u8>) -> io::Result<()> {
+        let start = buf.len();
+        buf.resize(start + payload.len() + NOISE_OVERHEAD, 0);
+        let len = self
+            .state
+            .write_message(payload, &mut buf[start..])
+            .map_err(|_| auth_failed())?;
+        buf.truncate(start + len);
+        Ok(())
+    }
+
+    /// 读取对端的握手消息，返回解密后的payload
+    ///
+    /// 读取失败时握手状态恢复到读取之前，可以继续读取之后的消息
+    pub fn read(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut payload = vec![0u8; message.len()];
+        let len = self
+            .state
+            .read_message(message, &mut payload)
+            .map_err(|_| auth_failed())?;
+        payload.truncate(len);
+        Ok(payload)
+    }
+
+    /// 对端的静态公钥，服务端在读取第一条消息后即可获得
+    pub fn remote_key(&self) -> Option<[u8; PUBLIC_KEY_LEN]> {
+        self.state
+            .get_remote_static()
+            .and_then(|key| key.try_into().ok())
+    }
+
+    /// 握手完成后导出双方的packet密钥
+    pub fn into_keys(mut self) -> io::Result<Keys> {
+        if !self.state.is_handshake_finished() {
+            return Err(auth_failed());
+        }
+
+        let (initiator, responder): ([u8; SECRET_LEN], [u8; SECRET_LEN]) =
+            self.state.dangerously_get_raw_split();
+        if self.state.is_initiator() {
+            Ok(Keys::new(initiator, responder))
+        } else {
+            Ok(Keys::new(responder, initiator))
+        }
+    }
+}
+
+fn params() -> NoiseParams {
+    NOISE_PARAMS.parse().expect("valid noise params")
+}
+
+#[test]
+fn test_noise_handshake() {
+    let server = Keypair::generate();
+    let client = Keypair::generate();
+
+    let mut initiator = NoiseHandshake::initiator(&client, &server.public).unwrap();
+    let mut responder = NoiseHandshake::responder(&server).unwrap();
+
+    let mut message = vec![];
+    initiator.write(b"client params", &mut message).unwrap();
+    assert_eq!(responder.read(&message).unwrap(), b"client params");
+    assert_eq!(responder.remote_key(), Some(client.public));
+
+    // 伪造的应答被拒绝，之后仍然可以读取真正的应答
+    assert!(initiator.read(&[7u8; 128]).is_err());
+
+    let mut message = vec![];
+    responder.write(b"server params", &mut message).unwrap();
+    assert_eq!(initiator.read(&message).unwrap(), b"server params");
+
+    // 双方导出的密钥能够互相解密
+    let mut client_keys = initiator.into_keys().unwrap();
+    let mut server_keys = responder.into_keys().unwrap();
+    let (phase, key) = client_keys.seal_key(None);
+    let mut buf = b"headerpayload".to_vec();
+    key.seal(0, &mut buf, 6);
+    assert!(server_keys.open(0, phase, &mut buf, 6).is_ok());
+
+    // 固定了错误的服务端公钥时握手失败
+    let other = Keypair::generate();
+    let mut initiator = NoiseHandshake::initiator(&client, &other.public).unwrap();
+    let mut responder = NoiseHandshake::responder(&server).unwrap();
+    let mut message = vec![];
+    initiator.write(b"client params", &mut message).unwrap();
+    assert!(responder.read(&message).is_err());
+}
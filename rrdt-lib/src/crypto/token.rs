@@ -0,0 +1,95 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TIMESTAMP_LEN: usize = 8;
+const MAC_LEN: usize = 32;
+
+/// 签发和验证Retry令牌的密钥，令牌与客户端地址及签发时间绑定
+///
+/// 验证所需的信息全部包含在令牌中，服务端在地址验证完成之前不需要保存任何状态
+pub struct TokenKey {
+    key: [u8; 32],
+}
+
+impl TokenKey {
+    /// 从`rng`中生成密钥，令牌只在同一个listener内有效
+    pub fn generate(rng: &mut impl RngCore) -> Self {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        Self { key }
+    }
+
+    /// 为`addr`签发令牌，格式为签发时间（UNIX秒）加上HMAC-SHA256
+    pub fn sign(&self, addr: &SocketAddr, now: SystemTime) -> Vec<u8> {
+        let timestamp = unix_secs(now).to_be_bytes();
+        let mut token = timestamp.to_vec();
+        token.extend_from_slice(&self.mac(addr, &timestamp).finalize().into_bytes());
+        token
+    }
+
+    /// 验证令牌是否由本密钥为`addr`签发，并且签发不超过`lifetime`
+    pub fn verify(
+        &self,
+        token: &[u8],
+        addr: &SocketAddr,
+        now: SystemTime,
+        lifetime: Duration,
+    ) -> bool {
+        if token.len() != TIMESTAMP_LEN + MAC_LEN {
+            return false;
+        }
+
+        let (timestamp, tag) = token.split_at(TIMESTAMP_LEN);
+        if self.mac(addr, timestamp).verify_slice(tag).is_err() {
+            return false;
+        }
+
+        let issued = u64::from_be_bytes(timestamp.try_into().expect("timestamp length"));
+        // 来自未来的令牌同样视为有效，避免时钟回拨导致握手失败
+        unix_secs(now).saturating_sub(issued) <= lifetime.as_secs()
+    }
+
+    fn mac(&self, addr: &SocketAddr, timestamp: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(timestamp);
+        match addr {
+            SocketAddr::V4(addr) => mac.update(&addr.ip().octets()),
+            SocketAddr::V6(addr) => mac.update(&addr.ip().octets()),
+        }
+        mac.update(&addr.port().to_be_bytes());
+        mac
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[test]
+fn test_token() {
+    let key = TokenKey::generate(&mut rand::thread_rng());
+    let addr = "127.0.0.1:4000".parse().unwrap();
+    let now = SystemTime::now();
+    let lifetime = Duration::from_secs(10);
+
+    let token = key.sign(&addr, now);
+    assert!(key.verify(&token, &addr, now, lifetime));
+
+    // 地址不同、令牌过期、被篡改或由其他密钥签发时都无法通过验证
+    let other_addr = "127.0.0.1:4001".parse().unwrap();
+    assert!(!key.verify(&token, &other_addr, now, lifetime));
+    assert!(!key.verify(&token, &addr, now + Duration::from_secs(11), lifetime));
+    let mut tampered = token.clone();
+    tampered[0] ^= 1;
+    assert!(!key.verify(&tampered, &addr, now, lifetime));
+    assert!(!TokenKey::generate(&mut rand::thread_rng()).verify(&token, &addr, now, lifetime));
+}
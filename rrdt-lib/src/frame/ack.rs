@@ -1,8 +1,12 @@
-use super::constant::DEFAULT_ACK_RANGES_LIMIT;
+use super::constant::{ACK_ECN_TYPE, ACK_TYPE, DEFAULT_ACK_RANGES_LIMIT};
 use crate::{
-    serializable::Serializable,
+    serializable::{varint_len, Serializable, VarIntBuf, VarIntBufMut},
+    socket::EcnCodepoint,
     types::PacketNum,
-    utils::{range_ext::RangeExt, range_set::RangeSet},
+    utils::{
+        range_ext::RangeExt,
+        range_set::{Iter, RangeSet},
+    },
 };
 use bytes::{Buf, BufMut};
 use std::time::Duration;
@@ -11,102 +15,228 @@ use std::time::Duration;
 pub struct AckFrame {
     /// 最大已确认包号
     pub largest_ack: PacketNum,
-    pub delay: Duration,
+    /// 最大已确认包号的packet从收到到发出ack的延迟，单位为2^ack_delay_exponent微秒
+    ///
+    /// ack_delay_exponent由发送ack frame的一方在传输参数中声明
+    pub delay: u64,
     /// 包含最大已确认包号的第一个ack range的长度
-    pub first_ack_range: u16,
+    pub first_ack_range: u64,
     pub ack_ranges: Vec<AckRange>,
+    /// 收到的各类ECN标记的packet数量，只有ACK_ECN类型的frame才会携带
+    pub ecn: Option<EcnCounts>,
 }
 
 impl AckFrame {
-    pub fn set_delay(&mut self, delay: Duration) {
-        self.delay = delay;
+    pub fn with_ecn(mut self, ecn: EcnCounts) -> Self {
+        self.ecn = Some(ecn);
+        self
     }
 
-    /// 减小ack frame的大小，方法为按顺序删除最老的ack ranges
-    pub fn reduce_to(&mut self, len: usize) {
-        while !self.ack_ranges.is_empty() && self.len() > len {
-            self.ack_ranges.pop();
+    /// 携带ECN计数的ack frame类型为ACK_ECN
+    pub fn ty(&self) -> u8 {
+        if self.ecn.is_some() {
+            ACK_ECN_TYPE
+        } else {
+            ACK_TYPE
         }
     }
-}
 
-impl Serializable for AckFrame {
-    fn decode(data: &mut impl Buf) -> Self {
-        let largest_ack = data.get_u64();
-        let delay = Duration::from_millis(data.get_u64());
-        let ack_range_count = data.get_u16();
-        let first_ack_range = data.get_u16();
+    /// 以本端声明的`ack_delay_exponent`编码ack延迟
+    pub fn set_delay(&mut self, delay: Duration, ack_delay_exponent: u8) {
+        self.delay = (delay.as_micros() as u64) >> ack_delay_exponent;
+    }
+
+    /// 以对端声明的`ack_delay_exponent`解码ack延迟
+    pub fn delay(&self, ack_delay_exponent: u8) -> Duration {
+        Duration::from_micros(self.delay.saturating_mul(1 << ack_delay_exponent))
+    }
+
+    /// 解码来自对端的ack frame，内容不完整或者ack range超出包号范围时返回`None`
+    ///
+    /// ack range的数量来自未经认证的数据，不能超过剩余数据最多能够容纳的数量
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let largest_ack = data.try_get_varint()?;
+        let delay = data.try_get_varint()?;
+        let ack_range_count = data.try_get_varint()?;
+        let first_ack_range = data.try_get_varint()?;
+        if ack_range_count > (data.remaining() / AckRange::min_len()) as u64 {
+            return None;
+        }
+
+        // 所有ack range都必须落在包号0之上，之后转换为`AckSpans`时才不会下溢
+        let mut smallest = (largest_ack + 1).checked_sub(first_ack_range)?;
         let mut ack_ranges = Vec::with_capacity(ack_range_count as usize);
         for _ in 0..ack_range_count {
-            let ack_range = AckRange::decode(data);
+            let ack_range = AckRange::try_decode(data)?;
+            smallest = smallest
+                .checked_sub(ack_range.gap)?
+                .checked_sub(ack_range.length)?;
             ack_ranges.push(ack_range);
         }
 
-        Self {
+        Some(Self {
             largest_ack,
             delay,
             first_ack_range,
             ack_ranges,
+            ecn: None,
+        })
+    }
+
+    /// 减小ack frame的大小，方法为按顺序删除最老的ack ranges
+    pub fn reduce_to(&mut self, len: usize) {
+        while !self.ack_ranges.is_empty() && self.len() > len {
+            self.ack_ranges.pop();
         }
     }
+}
+
+impl Serializable for AckFrame {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("malformed ack frame")
+    }
 
     fn encode(self, data: &mut impl BufMut) {
-        data.put_u64(self.largest_ack);
-        data.put_u64(self.delay.as_millis() as u64);
-        data.put_u16(self.ack_ranges.len() as u16);
-        data.put_u16(self.first_ack_range);
+        data.put_varint(self.largest_ack);
+        data.put_varint(self.delay);
+        data.put_varint(self.ack_ranges.len() as u64);
+        data.put_varint(self.first_ack_range);
         for ack_range in self.ack_ranges {
             ack_range.encode(data);
         }
+        if let Some(ecn) = self.ecn {
+            ecn.encode(data);
+        }
     }
 
     fn len(&self) -> usize {
-        Self::min_len()
+        // type
+        std::mem::size_of::<u8>()
+            // largest_ack
+            + varint_len(self.largest_ack)
+            // delay
+            + varint_len(self.delay)
+            // ack_range_count
+            + varint_len(self.ack_ranges.len() as u64)
+            // first_ack_range
+            + varint_len(self.first_ack_range)
             // ack_ranges
             + self
                 .ack_ranges
                 .iter()
                 .map(|ack_range| ack_range.len())
                 .sum::<usize>()
+            // ecn counts
+            + self.ecn.as_ref().map_or(0, |ecn| ecn.len())
     }
 
     fn min_len() -> usize {
         // type
         std::mem::size_of::<u8>() +
             // largest_ack
-            std::mem::size_of::<u64>()
+            1
             // delay
-            + std::mem::size_of::<u64>()
+            + 1
             // ack_range_count
-            + std::mem::size_of::<u16>()
+            + 1
             // first_ack_range
-            + std::mem::size_of::<u16>()
+            + 1
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct AckRange {
-    gap: u16,
-    length: u16,
+    gap: u64,
+    length: u64,
+}
+
+impl AckRange {
+    fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let gap = data.try_get_varint()?;
+        let length = data.try_get_varint()?;
+        Some(Self { gap, length })
+    }
 }
 
 impl Serializable for AckRange {
     fn decode(data: &mut impl Buf) -> Self {
-        let gap = data.get_u16();
-        let length = data.get_u16();
-        Self { gap, length }
+        Self::try_decode(data).expect("truncated ack range")
     }
 
     fn encode(self, data: &mut impl BufMut) {
-        data.put_u16(self.gap);
-        data.put_u16(self.length);
+        data.put_varint(self.gap);
+        data.put_varint(self.length);
+    }
+
+    fn len(&self) -> usize {
+        // gap
+        varint_len(self.gap)
+            // length
+            + varint_len(self.length)
     }
 
     fn min_len() -> usize {
         // gap
-        std::mem::size_of::<u16>()
+        1
             // length
-            + std::mem::size_of::<u16>()
+            + 1
+    }
+}
+
+/// 收到的各类ECN标记的packet的累计数量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EcnCounts {
+    pub ect0: u64,
+    pub ect1: u64,
+    pub ce: u64,
+}
+
+impl EcnCounts {
+    /// 记录一个带有`codepoint`标记的packet
+    pub fn record(&mut self, codepoint: EcnCodepoint) {
+        match codepoint {
+            EcnCodepoint::Ect0 => self.ect0 += 1,
+            EcnCodepoint::Ect1 => self.ect1 += 1,
+            EcnCodepoint::Ce => self.ce += 1,
+            EcnCodepoint::NotEct => {}
+        }
+    }
+
+    /// 是否收到过任何ECN标记的packet
+    pub fn is_empty(&self) -> bool {
+        self.ect0 == 0 && self.ect1 == 0 && self.ce == 0
+    }
+
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let ect0 = data.try_get_varint()?;
+        let ect1 = data.try_get_varint()?;
+        let ce = data.try_get_varint()?;
+        Some(Self { ect0, ect1, ce })
+    }
+}
+
+impl Serializable for EcnCounts {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("truncated ecn counts")
+    }
+
+    fn encode(self, data: &mut impl BufMut) {
+        data.put_varint(self.ect0);
+        data.put_varint(self.ect1);
+        data.put_varint(self.ce);
+    }
+
+    fn len(&self) -> usize {
+        varint_len(self.ect0) + varint_len(self.ect1) + varint_len(self.ce)
+    }
+
+    fn min_len() -> usize {
+        // ect0
+        1
+            // ect1
+            + 1
+            // ce
+            + 1
     }
 }
 
@@ -132,8 +262,9 @@ impl AckSpans {
         self.set.insert_one(x)
     }
 
-    pub fn contains(&self, x: u64) -> bool {
-        self.set.contains(x)
+    /// 按包号从小到大遍历所有已确认的区间
+    pub fn iter(&self) -> Iter<'_> {
+        self.set.iter()
     }
 }
 
@@ -149,7 +280,7 @@ impl From<AckFrame> for AckSpans {
         let mut set = RangeSet::new();
 
         let end = largest_ack + 1;
-        let start = end - first_ack_range as PacketNum;
+        let start = end - first_ack_range;
         let first_range = start..end;
 
         set.insert(first_range);
@@ -157,8 +288,8 @@ impl From<AckFrame> for AckSpans {
         for AckRange { gap, length } in ack_ranges {
             // TODO
             let current = set.first().unwrap();
-            let end = current.start - gap as PacketNum;
-            let start = end - length as PacketNum;
+            let end = current.start - gap;
+            let start = end - length;
             set.insert(start..end);
         }
 
@@ -179,12 +310,12 @@ impl Into<AckFrame> for AckSpans {
 
         let first = self.set.pop_back().unwrap();
         let largest_ack = first.end - 1;
-        let first_ack_range = (first.end - first.start) as u16;
+        let first_ack_range = first.end - first.start;
 
         let mut current = first;
         for range in self.set.iter().rev() {
-            let gap = (current.start - range.end) as u16;
-            let length = range.len() as u16;
+            let gap = current.start - range.end;
+            let length = range.len() as u64;
             ack_ranges.push(AckRange { gap, length });
             if ack_ranges.len() >= self.limit {
                 break;
@@ -220,3 +351,60 @@ fn test() {
 
     assert_eq!(spans, recv_spans);
 }
+
+#[test]
+fn test_delay() {
+    let mut frame = AckFrame::default();
+
+    // 默认以8微秒为单位，亚毫秒级的延迟不会被截断为0
+    frame.set_delay(Duration::from_micros(250), 3);
+    let mut buf = vec![];
+    frame.clone().encode(&mut buf);
+    let decoded = AckFrame::decode(&mut &buf[..]);
+    assert_eq!(decoded.delay(3), Duration::from_micros(248));
+
+    frame.set_delay(Duration::from_micros(50), 0);
+    assert_eq!(frame.delay(0), Duration::from_micros(50));
+}
+
+/// ack range的数量和长度都来自对端，超出剩余数据或包号范围的ack frame直接拒绝
+#[test]
+fn test_malformed() {
+    use super::Frame;
+
+    let encode = |largest_ack: u64, ack_range_count: u64, first_ack_range: u64| {
+        let mut buf = vec![];
+        buf.put_varint(largest_ack);
+        buf.put_varint(0);
+        buf.put_varint(ack_range_count);
+        buf.put_varint(first_ack_range);
+        buf.put_bytes(0, 16);
+        buf
+    };
+
+    let buf = encode(10, (1 << 62) - 1, 1);
+    assert!(AckFrame::try_decode(&mut &buf[..]).is_none());
+    let mut frame = vec![ACK_TYPE];
+    frame.extend_from_slice(&buf);
+    assert!(Frame::try_decode(&mut &frame[..]).is_none());
+
+    // 第一个ack range越过了包号0
+    let buf = encode(10, 0, 12);
+    assert!(AckFrame::try_decode(&mut &buf[..]).is_none());
+
+    // 超过u16范围的ack range原样保留
+    let frame = AckFrame {
+        largest_ack: 100_000,
+        first_ack_range: 70_000,
+        ack_ranges: vec![AckRange {
+            gap: 1,
+            length: 30_000,
+        }],
+        ..AckFrame::default()
+    };
+    let mut buf = vec![];
+    frame.encode(&mut buf);
+    let spans: AckSpans = AckFrame::try_decode(&mut &buf[..]).unwrap().into();
+    let ranges: Vec<_> = spans.iter().collect();
+    assert_eq!(ranges, vec![0..30_000, 30_001..100_001]);
+}
@@ -1,9 +1,13 @@
 use std::time::Duration;
 
+/// PADDING frame只有一个全零的类型字节，连续的PADDING frame会被作为一个整体解码
+pub const PADDING_TYPE: u8 = 0x00;
 pub const HANDSHAKE_TYPE: u8 = 0x01;
 pub const STREAM_TYPE: u8 = 0x02;
 pub const STREAM_FIN_TYPE: u8 = 0x03;
 pub const ACK_TYPE: u8 = 0x04;
 pub const MAX_STREAM_DATA_TYPE: u8 = 0x05;
+pub const PING_TYPE: u8 = 0x06;
+pub const ACK_ECN_TYPE: u8 = 0x07;
 
 pub const DEFAULT_ACK_RANGES_LIMIT: usize = 200;
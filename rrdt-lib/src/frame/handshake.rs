@@ -21,4 +21,9 @@ impl Serializable for HandshakeFrame {
             // params
             TransportParams::min_len()
     }
+
+    fn len(&self) -> usize {
+        // type
+        std::mem::size_of::<u8>() + self.params.len()
+    }
 }
@@ -1,12 +1,14 @@
 use self::{
-    ack::AckFrame,
+    ack::{AckFrame, EcnCounts},
     constant::*,
     handshake::HandshakeFrame,
     stream::{MaxStreamDataFrame, MaxStreamDataMeta, StreamDataFrame, StreamDataMeta},
 };
-use crate::{serializable::Serializable, types::StreamId};
+use crate::{connection::TransportParams, serializable::Serializable, types::StreamId};
 use bytes::{Buf, BufMut};
 
+pub(crate) use self::constant::PADDING_TYPE;
+
 pub mod ack;
 mod constant;
 pub mod handshake;
@@ -18,6 +20,10 @@ pub enum Frame {
     Ack(AckFrame),
     Stream(StreamDataFrame),
     MaxStreamData(MaxStreamDataFrame),
+    /// 不携带任何数据，仅用于引发对端的ack
+    Ping,
+    /// 用于填充packet，内容为连续的若干个零字节
+    Padding(usize),
 }
 
 impl Frame {
@@ -28,19 +34,42 @@ impl Frame {
             _ => None,
         }
     }
+
+    /// 解码来自对端的frame，类型未知或者内容不完整时返回`None`
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        if !data.has_remaining() {
+            return None;
+        }
+        let frame = match data.get_u8() {
+            HANDSHAKE_TYPE => Frame::Handshake(HandshakeFrame {
+                params: TransportParams::try_decode(data)?,
+            }),
+            ACK_TYPE => Frame::Ack(AckFrame::try_decode(data)?),
+            ACK_ECN_TYPE => {
+                let frame = AckFrame::try_decode(data)?;
+                Frame::Ack(frame.with_ecn(EcnCounts::try_decode(data)?))
+            }
+            STREAM_TYPE => Frame::Stream(StreamDataFrame::try_decode(data)?),
+            STREAM_FIN_TYPE => Frame::Stream(StreamDataFrame::try_decode(data)?.with_fin()),
+            MAX_STREAM_DATA_TYPE => Frame::MaxStreamData(MaxStreamDataFrame::try_decode(data)?),
+            PING_TYPE => Frame::Ping,
+            PADDING_TYPE => {
+                let mut len = 1;
+                while data.has_remaining() && data.chunk()[0] == PADDING_TYPE {
+                    data.advance(1);
+                    len += 1;
+                }
+                Frame::Padding(len)
+            }
+            _ => return None,
+        };
+        Some(frame)
+    }
 }
 
 impl Serializable for Frame {
     fn decode(data: &mut impl Buf) -> Self {
-        let ty = data.get_u8();
-        match ty {
-            HANDSHAKE_TYPE => Frame::Handshake(HandshakeFrame::decode(data)),
-            ACK_TYPE => Frame::Ack(AckFrame::decode(data)),
-            STREAM_TYPE => Frame::Stream(StreamDataFrame::decode(data)),
-            STREAM_FIN_TYPE => Frame::Stream(StreamDataFrame::decode(data).with_fin()),
-            MAX_STREAM_DATA_TYPE => Frame::MaxStreamData(MaxStreamDataFrame::decode(data)),
-            _ => panic!("unknown frame type: {}", ty),
-        }
+        Self::try_decode(data).expect("malformed frame")
     }
 
     fn encode(self, data: &mut impl BufMut) {
@@ -54,13 +83,19 @@ impl Serializable for Frame {
                 frame.encode(data);
             }
             Frame::Ack(frame) => {
-                data.put_u8(ACK_TYPE);
+                data.put_u8(frame.ty());
                 frame.encode(data);
             }
             Frame::MaxStreamData(frame) => {
                 data.put_u8(MAX_STREAM_DATA_TYPE);
                 frame.encode(data);
             }
+            Frame::Ping => {
+                data.put_u8(PING_TYPE);
+            }
+            Frame::Padding(len) => {
+                data.put_bytes(PADDING_TYPE, len);
+            }
         }
     }
 
@@ -75,6 +110,8 @@ impl Serializable for Frame {
             Frame::Stream(frame) => frame.len(),
             Frame::Ack(frame) => frame.len(),
             Frame::MaxStreamData(frame) => frame.len(),
+            Frame::Ping => Self::min_len(),
+            Frame::Padding(len) => *len,
         }
     }
 }
@@ -1,5 +1,8 @@
 use super::constant::*;
-use crate::{serializable::Serializable, types::StreamId};
+use crate::{
+    serializable::{varint_len, Serializable, VarIntBuf, VarIntBufMut},
+    types::StreamId,
+};
 use bytes::{Buf, BufMut, Bytes};
 use std::fmt::Debug;
 use std::{fmt::Formatter, ops::Range};
@@ -27,14 +30,39 @@ impl StreamDataFrame {
         }
     }
 
-    /// 将当前frame分成两个frame，保证分割出去的frame的大小恰好为`at`
+    /// 除去数据之外的部分编码后的长度
+    pub fn header_len(&self) -> usize {
+        self.len() - self.data.len()
+    }
+
+    /// 任意stream frame除去数据之外的部分编码后的最大长度，用于在数据读出之前预留空间
+    pub fn max_header_len() -> usize {
+        // type
+        std::mem::size_of::<u8>()
+            // id
+            + varint_len(StreamId::MAX as u64)
+            // offset
+            + std::mem::size_of::<u64>()
+            // length
+            + std::mem::size_of::<u64>()
+    }
+
+    /// 将当前frame分成两个frame，保证分割出去的frame的大小不超过`at`
+    ///
+    /// 由于length字段是变长的，分割出去的frame可能比`at`小一两个字节
     ///
     /// 被分割出去的frame必定不是fin frame
     pub fn split_to(&mut self, at: usize) -> Self {
-        assert!(at > Self::min_len());
+        assert!(at > self.header_len());
         assert!(at < self.len());
 
-        let data_at = at - Self::min_len();
+        // type + id + offset
+        let prefix_len =
+            std::mem::size_of::<u8>() + varint_len(self.id as u64) + varint_len(self.offset);
+        let mut data_at = at - prefix_len - 1;
+        while prefix_len + varint_len(data_at as u64) + data_at > at {
+            data_at -= 1;
+        }
 
         let result = StreamDataFrame {
             id: self.id,
@@ -54,32 +82,47 @@ impl StreamDataFrame {
             range: self.offset..self.offset + self.data.len() as u64,
         }
     }
-}
 
-impl Serializable for StreamDataFrame {
-    fn decode(data: &mut impl Buf) -> Self {
-        let id = data.get_u16();
-        let offset = data.get_u64();
-        let length = data.get_u64();
+    /// 数据长度超出剩余数据时返回`None`
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let id = data.try_get_varint()? as StreamId;
+        let offset = data.try_get_varint()?;
+        let length = data.try_get_varint()?;
+        if length > data.remaining() as u64 {
+            return None;
+        }
         let data = data.copy_to_bytes(length as usize);
 
-        Self {
+        Some(Self {
             id,
             offset,
             data,
             fin: false,
-        }
+        })
+    }
+}
+
+impl Serializable for StreamDataFrame {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("truncated stream frame")
     }
 
     fn encode(self, data: &mut impl BufMut) {
-        data.put_u16(self.id);
-        data.put_u64(self.offset);
-        data.put_u64(self.data.len() as u64);
+        data.put_varint(self.id as u64);
+        data.put_varint(self.offset);
+        data.put_varint(self.data.len() as u64);
         data.put_slice(&self.data);
     }
 
     fn len(&self) -> usize {
-        Self::min_len()
+        // type
+        std::mem::size_of::<u8>()
+            // id
+            + varint_len(self.id as u64)
+            // offset
+            + varint_len(self.offset)
+            // length
+            + varint_len(self.data.len() as u64)
             // data
             + self.data.len()
     }
@@ -88,11 +131,11 @@ impl Serializable for StreamDataFrame {
         // type
         std::mem::size_of::<u8>() +
             // id
-            std::mem::size_of::<u16>()
+            1
             // offset
-            + std::mem::size_of::<u64>()
+            + 1
             // length
-            + std::mem::size_of::<u64>()
+            + 1
     }
 }
 
@@ -122,28 +165,40 @@ impl MaxStreamDataFrame {
     pub fn meta(&self) -> MaxStreamDataMeta {
         MaxStreamDataMeta { id: self.id }
     }
+
+    pub(crate) fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let id = data.try_get_varint()? as StreamId;
+        let max_data = data.try_get_varint()?;
+        Some(Self { id, max_data })
+    }
 }
 
 impl Serializable for MaxStreamDataFrame {
     fn decode(data: &mut impl Buf) -> Self {
-        let id = data.get_u16();
-        let max_data = data.get_u64();
-
-        Self { id, max_data }
+        Self::try_decode(data).expect("truncated max stream data frame")
     }
 
     fn encode(self, data: &mut impl BufMut) {
-        data.put_u16(self.id);
-        data.put_u64(self.max_data);
+        data.put_varint(self.id as u64);
+        data.put_varint(self.max_data);
+    }
+
+    fn len(&self) -> usize {
+        // type
+        std::mem::size_of::<u8>()
+            // id
+            + varint_len(self.id as u64)
+            // max_data
+            + varint_len(self.max_data)
     }
 
     fn min_len() -> usize {
         // type
         std::mem::size_of::<u8>() +
             // id
-            std::mem::size_of::<u16>()
+            1
             // max_data
-            + std::mem::size_of::<u64>()
+            + 1
     }
 }
 
@@ -151,3 +206,34 @@ impl Serializable for MaxStreamDataFrame {
 pub struct MaxStreamDataMeta {
     pub id: StreamId,
 }
+
+#[test]
+fn test_split_to() {
+    use super::Frame;
+
+    let mut frame = StreamDataFrame {
+        id: 3,
+        offset: 16000,
+        data: Bytes::from(vec![0u8; 1000]),
+        fin: true,
+    };
+
+    for at in [frame.header_len() + 1, 66, 100, 500] {
+        let mut rest = frame.clone();
+        let splitted = rest.split_to(at);
+        assert!(splitted.len() <= at && splitted.len() + 2 >= at);
+        assert_eq!(rest.offset, frame.offset + splitted.data.len() as u64);
+
+        // len()必须与编码后的长度完全一致
+        let mut buf = vec![];
+        let len = splitted.len();
+        Frame::Stream(splitted).encode(&mut buf);
+        assert_eq!(buf.len(), len);
+    }
+
+    frame.data = frame.data.slice(..10);
+    let mut buf = vec![];
+    let len = frame.len();
+    Frame::Stream(frame).encode(&mut buf);
+    assert_eq!(buf.len(), len);
+}
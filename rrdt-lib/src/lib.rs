@@ -4,13 +4,19 @@
 mod congestion;
 mod connection;
 mod constant;
+mod crypto;
 mod frame;
 mod packet;
 mod serializable;
+mod socket;
+pub mod testing;
 mod types;
 mod utils;
 
+pub use congestion::{rtt_estimator::RttStats, CongestionAlgorithm};
 pub use connection::{
     CompressedParams, Connection, ConnectionBuildResult, ConnectionBuilder, ConnectionListener,
-    TransportParams,
+    ConnectionStats, TransportConfig, TransportParams,
 };
+pub use crypto::{Keypair, PUBLIC_KEY_LEN};
+pub use socket::{AsyncDatagramSocket, EcnCodepoint};
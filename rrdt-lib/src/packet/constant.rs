@@ -2,6 +2,34 @@ use crate::constant::K;
 
 pub const MAX_PACKET_SIZE: usize = 8 * K;
 
+/// 客户端的握手datagram至少需要填充到的大小，使服务端的应答不会远大于收到的数据
+pub const MIN_HANDSHAKE_DATAGRAM_SIZE: usize = 1200;
+
+/// 地址验证令牌的最大长度，声明的长度超过该值的握手packet直接丢弃
+pub const MAX_TOKEN_LEN: usize = 256;
+
 pub const HANDSHAKE_PACKET_TYPE: u8 = 0x01;
 pub const HANDSHAKE_DONE_PACKET_TYPE: u8 = 0x02;
 pub const COMPRESSED_PACKET_TYPE: u8 = 0x03;
+pub const RETRY_PACKET_TYPE: u8 = 0x04;
+
+/// short header首字节中固定为1的位
+pub const SHORT_HEADER_FIXED_BIT: u8 = 0x40;
+
+/// short header首字节的低2位表示packet number编码后的长度减1
+pub const PACKET_NUM_LEN_MASK: u8 = 0x03;
+
+/// short header首字节中的key phase位，标识packet使用的是哪一代密钥
+pub const KEY_PHASE_BIT: u8 = 0x04;
+
+/// packet number编码后的最大长度
+pub const MAX_PACKET_NUM_LEN: usize = 4;
+
+/// header保护中首字节被掩码覆盖的位
+pub const SHORT_HEADER_PROTECTED_BITS: u8 = 0x1f;
+
+/// header保护的采样位置相对于packet number起始位置的偏移，假定packet number总是占4字节
+pub const HP_SAMPLE_OFFSET: usize = 4;
+
+/// header保护的采样长度
+pub const HP_SAMPLE_LEN: usize = 16;
@@ -1,5 +1,11 @@
 use super::constant::*;
-use crate::{connection::CompressedParams, serializable::Serializable, TransportParams};
+use crate::{
+    connection::CompressedParams,
+    crypto::RANDOM_LEN,
+    frame::{Frame, PADDING_TYPE},
+    serializable::{varint_len, Serializable, VarIntBuf, VarIntBufMut},
+    TransportParams,
+};
 use bytes::{Buf, BufMut};
 
 #[derive(Debug, Clone)]
@@ -27,17 +33,32 @@ pub enum LongPacket {
     Handshake(HandshakePacket),
     HandshakeDone(HandshakeDonePacket),
     Compressed(CompressedPacket),
+    Retry(RetryPacket),
+}
+
+impl LongPacket {
+    /// 解码对端发来的long packet，类型未知或内容不完整时返回`None`
+    ///
+    /// 握手阶段的packet在通过认证之前就需要解码，任何输入都不能引起panic
+    pub fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        if !data.has_remaining() {
+            return None;
+        }
+        match data.get_u8() {
+            HANDSHAKE_PACKET_TYPE => HandshakePacket::try_decode(data).map(Self::Handshake),
+            HANDSHAKE_DONE_PACKET_TYPE => {
+                Some(Self::HandshakeDone(HandshakeDonePacket::decode(data)))
+            }
+            COMPRESSED_PACKET_TYPE => CompressedPacket::try_decode(data).map(Self::Compressed),
+            RETRY_PACKET_TYPE => RetryPacket::try_decode(data).map(Self::Retry),
+            _ => None,
+        }
+    }
 }
 
 impl Serializable for LongPacket {
     fn decode(data: &mut impl Buf) -> Self {
-        let ty = data.get_u8();
-        match ty {
-            HANDSHAKE_PACKET_TYPE => Self::Handshake(HandshakePacket::decode(data)),
-            HANDSHAKE_DONE_PACKET_TYPE => Self::HandshakeDone(HandshakeDonePacket::decode(data)),
-            COMPRESSED_PACKET_TYPE => Self::Compressed(CompressedPacket::decode(data)),
-            _ => panic!("Invalid packet type"),
-        }
+        Self::try_decode(data).expect("invalid long packet")
     }
 
     fn encode(self, data: &mut impl BufMut) {
@@ -54,6 +75,10 @@ impl Serializable for LongPacket {
                 data.put_u8(COMPRESSED_PACKET_TYPE);
                 packet.encode(data);
             }
+            Self::Retry(packet) => {
+                data.put_u8(RETRY_PACKET_TYPE);
+                packet.encode(data);
+            }
         }
     }
 
@@ -67,40 +92,104 @@ impl Serializable for LongPacket {
                 Self::Handshake(packet) => packet.len(),
                 Self::HandshakeDone(packet) => packet.len(),
                 Self::Compressed(packet) => packet.len(),
+                Self::Retry(packet) => packet.len(),
             }
     }
 }
 
 pub struct HandshakePacket {
     header: LongHeader,
+    /// 服务端在Retry中下发的地址验证令牌，首次握手时为空
+    token: Vec<u8>,
+    /// 每次握手随机生成，用于导出每个连接各自的密钥
+    random: [u8; RANDOM_LEN],
     params: TransportParams,
+    /// 末尾填充的字节数
+    padding: usize,
 }
 
 impl HandshakePacket {
-    pub fn new(params: TransportParams) -> Self {
+    /// `random`为本次握手的随机数
+    pub fn new(params: TransportParams, random: [u8; RANDOM_LEN]) -> Self {
         let header = LongHeader::new();
-        Self { header, params }
+        Self {
+            header,
+            token: vec![],
+            random,
+            params,
+            padding: 0,
+        }
+    }
+
+    /// 在末尾填充PADDING frame，使编码后的长度至少为`len`
+    pub fn pad_to(&mut self, len: usize) {
+        self.padding = 0;
+        self.padding = len.saturating_sub(self.len());
+    }
+
+    pub fn with_token(self, token: Vec<u8>) -> Self {
+        Self { token, ..self }
+    }
+
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    pub fn random(&self) -> [u8; RANDOM_LEN] {
+        self.random
     }
 
     pub fn into_params(self) -> TransportParams {
         self.params
     }
+
+    /// 内容不完整，或传输参数之后不全是填充时返回`None`
+    pub fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let header = LongHeader::decode(data);
+        let token = decode_token(data)?;
+        if data.remaining() < RANDOM_LEN {
+            return None;
+        }
+        let mut random = [0u8; RANDOM_LEN];
+        data.copy_to_slice(&mut random);
+        let params = TransportParams::try_decode(data)?;
+        let padding = data.remaining();
+        while data.has_remaining() {
+            if data.get_u8() != PADDING_TYPE {
+                return None;
+            }
+        }
+        Some(Self {
+            header,
+            token,
+            random,
+            params,
+            padding,
+        })
+    }
 }
 
 impl Serializable for HandshakePacket {
     fn decode(data: &mut impl Buf) -> Self {
-        let header = LongHeader::decode(data);
-        let params = TransportParams::decode(data);
-        Self { header, params }
+        Self::try_decode(data).expect("invalid handshake packet")
     }
 
     fn encode(self, data: &mut impl BufMut) {
         self.header.encode(data);
+        encode_token(&self.token, data);
+        data.put_slice(&self.random);
         self.params.encode(data);
+        if self.padding > 0 {
+            Frame::Padding(self.padding).encode(data);
+        }
     }
 
     fn min_len() -> usize {
-        LongHeader::min_len() + TransportParams::min_len()
+        LongHeader::min_len() + 1 + RANDOM_LEN + TransportParams::min_len()
+    }
+
+    fn len(&self) -> usize {
+        self.header.len() + token_len(&self.token) + RANDOM_LEN + self.params.len() + self.padding
     }
 }
 
@@ -120,11 +209,17 @@ impl CompressedPacket {
     }
 }
 
+impl CompressedPacket {
+    pub fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let header = LongHeader::decode(data);
+        let params = CompressedParams::try_decode(data)?;
+        Some(Self { header, params })
+    }
+}
+
 impl Serializable for CompressedPacket {
     fn decode(data: &mut impl Buf) -> Self {
-        let header = LongHeader::decode(data);
-        let params = CompressedParams::decode(data);
-        Self { header, params }
+        Self::try_decode(data).expect("invalid compressed packet")
     }
 
     fn encode(self, data: &mut impl BufMut) {
@@ -137,6 +232,96 @@ impl Serializable for CompressedPacket {
     }
 }
 
+/// 服务端要求客户端验证地址时的应答，客户端需要携带其中的令牌重新发起握手
+pub struct RetryPacket {
+    header: LongHeader,
+    token: Vec<u8>,
+    /// 每个Retry随机生成，设置了PSK时用于生成认证标签的nonce
+    random: [u8; RANDOM_LEN],
+}
+
+impl RetryPacket {
+    pub fn new(token: Vec<u8>, random: [u8; RANDOM_LEN]) -> Self {
+        let header = LongHeader::new();
+        Self {
+            header,
+            token,
+            random,
+        }
+    }
+
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    pub fn random(&self) -> [u8; RANDOM_LEN] {
+        self.random
+    }
+
+    pub fn into_token(self) -> Vec<u8> {
+        self.token
+    }
+}
+
+impl RetryPacket {
+    pub fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        let header = LongHeader::decode(data);
+        let token = decode_token(data)?;
+        if data.remaining() < RANDOM_LEN {
+            return None;
+        }
+        let mut random = [0u8; RANDOM_LEN];
+        data.copy_to_slice(&mut random);
+        Some(Self {
+            header,
+            token,
+            random,
+        })
+    }
+}
+
+impl Serializable for RetryPacket {
+    fn decode(data: &mut impl Buf) -> Self {
+        Self::try_decode(data).expect("invalid retry packet")
+    }
+
+    fn encode(self, data: &mut impl BufMut) {
+        self.header.encode(data);
+        encode_token(&self.token, data);
+        data.put_slice(&self.random);
+    }
+
+    fn min_len() -> usize {
+        LongHeader::min_len() + 1 + RANDOM_LEN
+    }
+
+    fn len(&self) -> usize {
+        self.header.len() + token_len(&self.token) + RANDOM_LEN
+    }
+}
+
+/// 令牌以varint编码的长度作为前缀，长度超出剩余数据或[`MAX_TOKEN_LEN`]时返回`None`
+///
+/// 长度来自未经认证的数据，必须先检查再分配
+fn decode_token(data: &mut impl Buf) -> Option<Vec<u8>> {
+    let len = data.try_get_varint()?;
+    if len > data.remaining().min(MAX_TOKEN_LEN) as u64 {
+        return None;
+    }
+    let mut token = vec![0u8; len as usize];
+    data.copy_to_slice(&mut token);
+    Some(token)
+}
+
+fn encode_token(token: &[u8], data: &mut impl BufMut) {
+    data.put_varint(token.len() as u64);
+    data.put_slice(token);
+}
+
+fn token_len(token: &[u8]) -> usize {
+    varint_len(token.len() as u64) + token.len()
+}
+
 pub struct HandshakeDonePacket {
     header: LongHeader,
 }
@@ -162,3 +347,50 @@ impl Serializable for HandshakeDonePacket {
         LongHeader::min_len()
     }
 }
+
+/// 类型未知、内容不完整或带有多余数据的long packet解码为`None`，而不是panic
+#[test]
+fn test_malformed() {
+    let packet = LongPacket::Handshake(
+        HandshakePacket::new(TransportParams::default(), [7; RANDOM_LEN]).with_token(vec![1, 2, 3]),
+    );
+    let mut data = vec![];
+    packet.encode(&mut data);
+    data.extend_from_slice(&[PADDING_TYPE; 16]);
+    let Some(LongPacket::Handshake(packet)) = LongPacket::try_decode(&mut &data[..]) else {
+        panic!("valid handshake packet rejected");
+    };
+    assert_eq!(packet.token(), [1, 2, 3]);
+    assert_eq!(packet.random(), [7; RANDOM_LEN]);
+
+    // 任意位置截断
+    for len in 0..data.len() - 16 {
+        assert!(LongPacket::try_decode(&mut &data[..len]).is_none());
+    }
+
+    // 未知类型和填充之后的多余数据
+    assert!(LongPacket::try_decode(&mut &[0xff, 0, 0][..]).is_none());
+    data.push(0x06);
+    assert!(LongPacket::try_decode(&mut &data[..]).is_none());
+
+    let mut retry = vec![];
+    LongPacket::Retry(RetryPacket::new(vec![9; 32], [3; RANDOM_LEN])).encode(&mut retry);
+    assert!(LongPacket::try_decode(&mut &retry[..retry.len() - 1]).is_none());
+}
+
+/// 声明的令牌长度超出剩余数据或上限时直接拒绝，不会按声明的长度分配内存
+#[test]
+fn test_token_len() {
+    use crate::serializable::VarIntBufMut;
+
+    for len in [u32::MAX as u64, (MAX_TOKEN_LEN + 1) as u64] {
+        let mut data = vec![RETRY_PACKET_TYPE];
+        data.put_varint(len);
+        data.resize(MAX_TOKEN_LEN * 2, 0);
+        assert!(LongPacket::try_decode(&mut &data[..]).is_none());
+    }
+
+    let mut data = vec![];
+    LongPacket::Retry(RetryPacket::new(vec![1; MAX_TOKEN_LEN], [3; RANDOM_LEN])).encode(&mut data);
+    assert!(LongPacket::try_decode(&mut &data[..]).is_some());
+}
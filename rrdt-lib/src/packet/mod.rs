@@ -3,5 +3,5 @@ mod long;
 mod short;
 
 pub use constant::*;
-pub use long::{CompressedPacket, HandshakePacket, LongPacket};
-pub use short::{Packet, PacketMeta};
+pub use long::{CompressedPacket, HandshakePacket, LongPacket, RetryPacket};
+pub use short::{protect_header, unprotect_header, Header, Packet, PacketMeta};
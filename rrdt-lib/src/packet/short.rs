@@ -1,5 +1,6 @@
 use super::constant::*;
 use crate::{
+    crypto::{HeaderKey, TAG_LEN},
     frame::{Frame, FrameMeta},
     serializable::Serializable,
     types::PacketNum,
@@ -11,21 +12,21 @@ use tokio::time::Instant;
 pub struct Packet {
     header: Header,
     frames: Vec<Frame>,
+    /// 是否是用于路径MTU探测的packet
+    is_mtu_probe: bool,
 }
 
 impl Packet {
     pub fn new(packet_num: PacketNum) -> Self {
         let header = Header::new(packet_num);
-        Self {
-            header,
-            frames: vec![],
-        }
+        Self::with_header(header)
     }
 
     pub fn with_header(header: Header) -> Self {
         Self {
             header,
             frames: vec![],
+            is_mtu_probe: false,
         }
     }
 
@@ -33,10 +34,72 @@ impl Packet {
         Self { frames, ..self }
     }
 
+    /// 在header已经被单独解码的情况下，解码剩余的所有frame
+    pub fn decode_frames(header: Header, data: &mut impl Buf) -> Self {
+        Self::try_decode_frames(header, data).expect("malformed frames")
+    }
+
+    /// 解码来自对端的frame，其中任意一个frame无法解码时整个packet都应当丢弃
+    pub fn try_decode_frames(header: Header, data: &mut impl Buf) -> Option<Self> {
+        let mut frames = Vec::new();
+        // 由于packet中并没有frame的数量信息，所以这里只能将data中剩余的全部数据认为是frame
+        while data.has_remaining() {
+            let frame = Frame::try_decode(data)?;
+            frames.push(frame);
+        }
+
+        Some(Self::with_header(header).with_frames(frames))
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.header.len()
+    }
+
+    /// 开启header保护时，保证packet number之后至少有`HP_SAMPLE_OFFSET`字节，使得采样不会越过认证标签
+    ///
+    /// payload过短时通过加长packet number的编码来满足
+    pub fn reserve_sample(&mut self) {
+        let payload_len = self.len() - self.header.len();
+        let packet_num_len = HP_SAMPLE_OFFSET.saturating_sub(payload_len);
+        self.header.packet_num_len = self.header.packet_num_len.max(packet_num_len);
+    }
+
     pub fn packet_num(&self) -> PacketNum {
         self.header.packet_num()
     }
 
+    /// 根据对端已确认的最大packet number，选用尽可能短的packet number编码长度
+    ///
+    /// packet末尾有填充时相应地加长填充，保持packet的长度不变
+    pub fn truncate_packet_num(&mut self, largest_acked: Option<PacketNum>) {
+        let header_len = self.header.len();
+        self.header.truncate_packet_num(largest_acked);
+        if let Some(Frame::Padding(len)) = self.frames.last_mut() {
+            *len += header_len.saturating_sub(self.header.len());
+        }
+    }
+
+    /// 在末尾添加PADDING frame，使packet编码后的长度至少为`len`
+    pub fn pad_to(&mut self, len: usize) {
+        let padding = len.saturating_sub(self.len());
+        if padding > 0 {
+            self.frames.push(Frame::Padding(padding));
+        }
+    }
+
+    pub fn set_key_phase(&mut self, key_phase: bool) {
+        self.header.key_phase = key_phase;
+    }
+
+    /// 标记为路径MTU探测packet，探测packet丢失时不会引起拥塞控制的反应
+    pub fn set_mtu_probe(&mut self) {
+        self.is_mtu_probe = true;
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
     pub fn into_frames(self) -> Vec<Frame> {
         self.frames
     }
@@ -54,6 +117,7 @@ impl Packet {
             .filter_map(|frame| frame.meta())
             .collect();
         let is_ack_eliciting = self.is_ack_eliciting();
+        let is_mtu_probe = self.is_mtu_probe;
 
         PacketMeta {
             packet_num,
@@ -61,19 +125,20 @@ impl Packet {
             sent,
             bytes,
             is_ack_eliciting,
+            is_mtu_probe,
         }
     }
 
-    /// packet中最多还可以容纳多少字节
-    pub fn remaining(&self) -> usize {
-        MAX_PACKET_SIZE - self.len()
+    /// datagram大小为`mtu`时，packet中最多还可以容纳多少字节，总是为AEAD的认证标签预留空间
+    pub fn remaining(&self, mtu: usize) -> usize {
+        mtu.saturating_sub(TAG_LEN + self.len())
     }
 
     /// 包含非ACK、PADDING和CONNECTION_CLOSE帧的packet是ack eliciting的
     pub fn is_ack_eliciting(&self) -> bool {
         self.frames
             .iter()
-            .any(|frame| !matches!(frame, Frame::Ack(_)))
+            .any(|frame| !matches!(frame, Frame::Ack(_) | Frame::Padding(_)))
     }
 
     pub fn is_empty(&self) -> bool {
@@ -84,15 +149,7 @@ impl Packet {
 impl Serializable for Packet {
     fn decode(data: &mut impl Buf) -> Self {
         let header = Header::decode(data);
-
-        let mut frames = Vec::new();
-        // 由于packet中并没有frame的数量信息，所以这里只能将data中剩余的全部数据认为是frame
-        while data.has_remaining() {
-            let frame = Frame::decode(data);
-            frames.push(frame);
-        }
-
-        Self::with_header(header).with_frames(frames)
+        Self::decode_frames(header, data)
     }
 
     fn encode(self, data: &mut impl BufMut) {
@@ -107,7 +164,7 @@ impl Serializable for Packet {
     }
 
     fn len(&self) -> usize {
-        Self::min_len() + self.frames.iter().map(|frame| frame.len()).sum::<usize>()
+        self.header.len() + self.frames.iter().map(|frame| frame.len()).sum::<usize>()
     }
 }
 
@@ -122,36 +179,257 @@ pub struct PacketMeta {
     pub bytes: u64,
     /// packet是否是ack eliciting的
     pub is_ack_eliciting: bool,
+    /// packet是否是路径MTU探测packet
+    pub is_mtu_probe: bool,
 }
 
+/// short header，由一个标志字节和1~4字节的packet number组成
+///
+/// packet number只编码低位的若干字节，接收方根据已收到的最大packet number还原，见RFC9000 17.1节
 #[derive(Debug, Clone)]
 pub struct Header {
+    /// 发送时为完整的packet number；解码后、还原之前只有低`packet_num_len`字节有效
     packet_num: PacketNum,
+    packet_num_len: usize,
+    /// 密钥更新时翻转，接收方据此选择解密使用的密钥
+    key_phase: bool,
 }
 
 impl Header {
+    /// 新建的packet先按最大长度编码packet number，发送前再根据对端的确认情况缩短
     pub fn new(packet_num: PacketNum) -> Self {
-        Self { packet_num }
+        Self {
+            packet_num,
+            packet_num_len: MAX_PACKET_NUM_LEN,
+            key_phase: false,
+        }
     }
 
     pub fn packet_num(&self) -> PacketNum {
         self.packet_num
     }
+
+    pub fn key_phase(&self) -> bool {
+        self.key_phase
+    }
+
+    pub fn truncate_packet_num(&mut self, largest_acked: Option<PacketNum>) {
+        self.packet_num_len = packet_num_len(self.packet_num, largest_acked);
+    }
+
+    pub fn reconstruct_packet_num(&mut self, largest_received: Option<PacketNum>) {
+        let expected = largest_received.map_or(0, |largest| largest + 1);
+        self.packet_num = decode_packet_num(expected, self.packet_num, self.packet_num_len);
+    }
+
+    /// 首字节声明的packet number长度超出剩余数据，或者不是short header时返回`None`
+    pub fn try_decode(data: &mut impl Buf) -> Option<Self> {
+        if !data.has_remaining() {
+            return None;
+        }
+        let flags = data.get_u8();
+        // 握手阶段的long header packet没有固定位，例如客户端重传的握手packet
+        if flags & SHORT_HEADER_FIXED_BIT == 0 {
+            return None;
+        }
+        let packet_num_len = (flags & PACKET_NUM_LEN_MASK) as usize + 1;
+        if data.remaining() < packet_num_len {
+            return None;
+        }
+        let key_phase = flags & KEY_PHASE_BIT != 0;
+        let packet_num = data.get_uint(packet_num_len);
+
+        Some(Self {
+            packet_num,
+            packet_num_len,
+            key_phase,
+        })
+    }
 }
 
 impl Serializable for Header {
     fn decode(data: &mut impl Buf) -> Self {
-        let packet_num = data.get_u64();
-
-        Self { packet_num }
+        Self::try_decode(data).expect("truncated short header")
     }
 
     fn encode(self, buf: &mut impl BufMut) {
-        buf.put_u64(self.packet_num);
+        let key_phase = if self.key_phase { KEY_PHASE_BIT } else { 0 };
+        buf.put_u8(SHORT_HEADER_FIXED_BIT | key_phase | (self.packet_num_len - 1) as u8);
+        let mask = (1 << (8 * self.packet_num_len)) - 1;
+        buf.put_uint(self.packet_num & mask, self.packet_num_len);
     }
 
     fn min_len() -> usize {
-        // packet_num
-        std::mem::size_of::<u64>()
+        // flags
+        std::mem::size_of::<u8>()
+            // packet_num
+            + 1
+    }
+
+    fn len(&self) -> usize {
+        // flags
+        std::mem::size_of::<u8>()
+            // packet_num
+            + self.packet_num_len
+    }
+}
+
+/// 对已经加密的packet施加header保护，掩盖首字节的低位以及packet number
+///
+/// 采样位置固定为packet number起始位置之后4字节，需要先调用[`Packet::reserve_sample`]保证packet足够长
+pub fn protect_header(buf: &mut [u8], key: &HeaderKey) {
+    let packet_num_len = (buf[0] & PACKET_NUM_LEN_MASK) as usize + 1;
+    let mask = header_mask(buf, key);
+
+    buf[0] ^= mask[0] & SHORT_HEADER_PROTECTED_BITS;
+    for (byte, mask) in buf[1..1 + packet_num_len].iter_mut().zip(&mask[1..]) {
+        *byte ^= mask;
+    }
+}
+
+/// 去除header保护，packet过短无法采样时返回`None`
+pub fn unprotect_header(buf: &mut [u8], key: &HeaderKey) -> Option<()> {
+    if buf.len() < 1 + HP_SAMPLE_OFFSET + HP_SAMPLE_LEN {
+        return None;
+    }
+
+    let mask = header_mask(buf, key);
+
+    buf[0] ^= mask[0] & SHORT_HEADER_PROTECTED_BITS;
+    let packet_num_len = (buf[0] & PACKET_NUM_LEN_MASK) as usize + 1;
+    for (byte, mask) in buf[1..1 + packet_num_len].iter_mut().zip(&mask[1..]) {
+        *byte ^= mask;
+    }
+
+    Some(())
+}
+
+fn header_mask(buf: &[u8], key: &HeaderKey) -> [u8; 5] {
+    let start = 1 + HP_SAMPLE_OFFSET;
+    let sample = buf[start..start + HP_SAMPLE_LEN]
+        .try_into()
+        .expect("sample length");
+    key.mask(sample)
+}
+
+/// 编码`packet_num`所需的字节数，需要能够覆盖两倍于尚未被确认的packet number范围，见RFC9000附录A.2
+fn packet_num_len(packet_num: PacketNum, largest_acked: Option<PacketNum>) -> usize {
+    let unacked = match largest_acked {
+        Some(largest_acked) => packet_num.saturating_sub(largest_acked),
+        None => packet_num + 1,
+    };
+    let bits = u64::BITS - unacked.leading_zeros() + 1;
+    (bits.div_ceil(8) as usize).min(MAX_PACKET_NUM_LEN)
+}
+
+/// 根据期望收到的下一个packet number，将截断的packet number还原为完整值，见RFC9000附录A.3
+fn decode_packet_num(expected: PacketNum, truncated: PacketNum, len: usize) -> PacketNum {
+    let win = 1u64 << (8 * len);
+    let half_win = win / 2;
+    let mask = win - 1;
+
+    let candidate = (expected & !mask) | truncated;
+    if candidate + half_win <= expected && candidate < (1 << 62) - win {
+        candidate + win
+    } else if candidate > expected + half_win && candidate >= win {
+        candidate - win
+    } else {
+        candidate
+    }
+}
+
+#[test]
+fn test_packet_num() {
+    // RFC9000附录A.2中的例子
+    assert_eq!(packet_num_len(0xac5c02, Some(0xabe8b3)), 2);
+    assert_eq!(packet_num_len(0xace8fe, Some(0xabe8b3)), 3);
+    assert_eq!(packet_num_len(0, None), 1);
+
+    // RFC9000附录A.3中的例子
+    assert_eq!(decode_packet_num(0xa82f30eb, 0x9b32, 2), 0xa82f9b32);
+
+    for (packet_num, largest_acked) in [(0, None), (300, Some(290)), (70000, Some(1000))] {
+        let mut header = Header::new(packet_num);
+        header.truncate_packet_num(largest_acked);
+        let len = header.len();
+
+        let mut buf = vec![];
+        header.encode(&mut buf);
+        assert_eq!(buf.len(), len);
+
+        let mut header = Header::decode(&mut &buf[..]);
+        header.reconstruct_packet_num(largest_acked);
+        assert_eq!(header.packet_num(), packet_num);
+    }
+}
+
+#[test]
+fn test_padding() {
+    let mut packet = Packet::new(1000).with_frames(vec![Frame::Ping]);
+    packet.pad_to(1200);
+    assert_eq!(packet.len(), 1200);
+
+    // 缩短packet number后长度不变，解码时填充作为一个整体
+    packet.truncate_packet_num(Some(999));
+    assert_eq!(packet.len(), 1200);
+    let mut buf = vec![];
+    packet.encode(&mut buf);
+    assert_eq!(buf.len(), 1200);
+    let packet = Packet::decode(&mut &buf[..]);
+    assert!(matches!(
+        packet.frames(),
+        [Frame::Ping, Frame::Padding(1197)]
+    ));
+    assert!(packet.is_ack_eliciting());
+
+    // 只包含填充的packet不是ack eliciting的
+    let mut packet = Packet::new(0);
+    packet.pad_to(100);
+    assert!(!packet.is_ack_eliciting());
+}
+
+#[test]
+fn test_header_protection() {
+    use crate::crypto::Keys;
+
+    let mut keys = Keys::derive(b"secret", &[1; 32], &[2; 32], true);
+
+    for frames in [vec![Frame::Ping], vec![Frame::Ping; 100]] {
+        let (key_phase, key) = keys.seal_key(None);
+        let mut packet = Packet::new(0x1234).with_frames(frames);
+        packet.set_key_phase(!key_phase);
+        packet.truncate_packet_num(Some(0x1200));
+        packet.reserve_sample();
+
+        let header_len = packet.header_len();
+        let mut buf = vec![];
+        packet.clone().encode(&mut buf);
+        key.seal(0x1234, &mut buf, header_len);
+        let sealed = buf.clone();
+
+        protect_header(&mut buf, keys.local_header());
+        assert_ne!(buf[..header_len], sealed[..header_len]);
+
+        // 去除保护后与保护前完全一致，并且能够正确还原packet number
+        unprotect_header(&mut buf, keys.local_header()).unwrap();
+        assert_eq!(buf, sealed);
+
+        let mut header = Header::decode(&mut &buf[..]);
+        header.reconstruct_packet_num(Some(0x1233));
+        assert_eq!(header.packet_num(), 0x1234);
+        assert_eq!(header.len(), header_len);
+        assert_eq!(header.key_phase(), !key_phase);
+    }
+}
+
+/// 首字节声明的packet number长度超出datagram的长度时不会越界读取
+#[test]
+fn test_truncated_header() {
+    for len in 1..=4 {
+        let mut buf = vec![SHORT_HEADER_FIXED_BIT | (len - 1) as u8];
+        buf.resize(len, 0);
+        assert!(Header::try_decode(&mut &buf[..]).is_none());
+        buf.push(0);
+        assert_eq!(Header::try_decode(&mut &buf[..]).unwrap().len(), len + 1);
     }
 }
@@ -19,3 +19,85 @@ pub trait Serializable {
         Self::min_len()
     }
 }
+
+/// QUIC变长整数（RFC9000 16节）能够表示的最大值
+pub const VARINT_MAX: u64 = (1 << 62) - 1;
+
+/// 编码`value`所需的字节数，首字节的高2位表示长度为1、2、4或8字节
+pub fn varint_len(value: u64) -> usize {
+    match value {
+        0..=0x3f => 1,
+        0x40..=0x3fff => 2,
+        0x4000..=0x3fff_ffff => 4,
+        _ => {
+            debug_assert!(value <= VARINT_MAX, "varint overflow: {}", value);
+            8
+        }
+    }
+}
+
+/// 从`Buf`中读取变长整数
+pub trait VarIntBuf: Buf {
+    fn get_varint(&mut self) -> u64 {
+        let first = self.get_u8();
+        let rest = (first & 0x3f) as u64;
+        match first >> 6 {
+            0 => rest,
+            1 => rest << 8 | self.get_u8() as u64,
+            2 => rest << 24 | self.get_uint(3),
+            _ => rest << 56 | self.get_uint(7),
+        }
+    }
+
+    /// 读取变长整数，剩余数据不足时返回`None`
+    fn try_get_varint(&mut self) -> Option<u64> {
+        if !self.has_remaining() {
+            return None;
+        }
+        let len = 1 << (self.chunk()[0] >> 6);
+        if self.remaining() < len {
+            return None;
+        }
+        Some(self.get_varint())
+    }
+}
+
+impl<T: Buf + ?Sized> VarIntBuf for T {}
+
+/// 向`BufMut`中写入变长整数
+pub trait VarIntBufMut: BufMut {
+    fn put_varint(&mut self, value: u64) {
+        match varint_len(value) {
+            1 => self.put_u8(value as u8),
+            2 => self.put_u16(0x4000 | value as u16),
+            4 => self.put_u32(0x8000_0000 | value as u32),
+            _ => self.put_u64(0xc000_0000_0000_0000 | value),
+        }
+    }
+}
+
+impl<T: BufMut + ?Sized> VarIntBufMut for T {}
+
+#[test]
+fn test_varint() {
+    for (value, len) in [
+        (0, 1),
+        (63, 1),
+        (64, 2),
+        (16383, 2),
+        (16384, 4),
+        (1073741823, 4),
+        (1073741824, 8),
+        (VARINT_MAX, 8),
+    ] {
+        let mut buf = vec![];
+        buf.put_varint(value);
+        assert_eq!(buf.len(), len);
+        assert_eq!(varint_len(value), len);
+        assert_eq!((&buf[..]).get_varint(), value);
+    }
+
+    // RFC9000 附录A.1中的例子
+    assert_eq!((&[0x7b, 0xbd][..]).get_varint(), 15293);
+    assert_eq!((&[0x9d, 0x7f, 0x3e, 0x7d][..]).get_varint(), 494878333);
+}
@@ -0,0 +1,223 @@
+use super::EcnCodepoint;
+use tokio::{io, net::UdpSocket};
+
+/// 一次系统调用最多收发的datagram数量
+pub const BATCH_SIZE: usize = 32;
+
+/// 发送一批datagram，返回发送过程中使用的系统调用次数
+///
+/// Linux上通过`sendmmsg`合并发送，其他平台上逐个发送。单个datagram发送失败时直接跳过，由丢包检测负责重传
+pub async fn send_batch(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> usize {
+    imp::send_batch(socket, datagrams).await
+}
+
+/// 接收一批datagram，依次写入`bufs`中，返回每个datagram的长度和ECN标记
+///
+/// 至少会等到一个datagram，Linux上通过`recvmmsg`一次读出所有已经到达的datagram
+pub async fn recv_batch(
+    socket: &UdpSocket,
+    bufs: &mut [Vec<u8>],
+) -> io::Result<Vec<(usize, EcnCodepoint)>> {
+    imp::recv_batch(socket, bufs).await
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{super::ecn, EcnCodepoint, BATCH_SIZE};
+    use std::{
+        mem,
+        os::fd::AsRawFd,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tokio::{
+        io::{self, Interest},
+        net::UdpSocket,
+    };
+
+    /// 部分环境（如一些沙箱）没有实现`sendmmsg`/`recvmmsg`，此时退回到逐个收发
+    static MMSG_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+    fn unsupported(err: &io::Error) -> bool {
+        if err.raw_os_error() == Some(libc::ENOSYS) {
+            MMSG_UNSUPPORTED.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sendmmsg(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> io::Result<usize> {
+        let mut iovs: Vec<_> = datagrams
+            .iter()
+            .map(|datagram| libc::iovec {
+                iov_base: datagram.as_ptr() as *mut libc::c_void,
+                iov_len: datagram.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .map(|iov| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        let n = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                msgs.len() as libc::c_uint,
+                0,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    pub async fn send_batch(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> usize {
+        let mut calls = 0;
+        let mut sent = 0;
+
+        while sent < datagrams.len() {
+            if MMSG_UNSUPPORTED.load(Ordering::Relaxed) {
+                let _ = socket.send(&datagrams[sent]).await;
+                sent += 1;
+            } else {
+                let end = datagrams.len().min(sent + BATCH_SIZE);
+                let batch = &datagrams[sent..end];
+                match socket
+                    .async_io(Interest::WRITABLE, || sendmmsg(socket, batch))
+                    .await
+                {
+                    Ok(n) => sent += n,
+                    Err(err) if unsupported(&err) => continue,
+                    // 只有第一个datagram就发送失败时才会返回错误，跳过它继续发送剩余的datagram
+                    Err(_) => sent += 1,
+                }
+            }
+            calls += 1;
+        }
+
+        calls
+    }
+
+    fn recvmmsg(
+        socket: &UdpSocket,
+        bufs: &mut [Vec<u8>],
+    ) -> io::Result<Vec<(usize, EcnCodepoint)>> {
+        let len = bufs.len().min(BATCH_SIZE);
+        let mut iovs: Vec<_> = bufs[..len]
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        // control message需要按cmsghdr对齐
+        let mut controls = vec![[0u64; 8]; len];
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .zip(controls.iter_mut())
+            .map(|(iov, control)| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                msg.msg_hdr.msg_controllen = mem::size_of_val(control) as _;
+                msg
+            })
+            .collect();
+
+        let n = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                len as libc::c_uint,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(msgs[..n as usize]
+            .iter()
+            .map(|msg| (msg.msg_len as usize, ecn::control_ecn(&msg.msg_hdr)))
+            .collect())
+    }
+
+    pub async fn recv_batch(
+        socket: &UdpSocket,
+        bufs: &mut [Vec<u8>],
+    ) -> io::Result<Vec<(usize, EcnCodepoint)>> {
+        if !MMSG_UNSUPPORTED.load(Ordering::Relaxed) {
+            match socket
+                .async_io(Interest::READABLE, || recvmmsg(socket, bufs))
+                .await
+            {
+                Err(err) if unsupported(&err) => {}
+                result => return result,
+            }
+        }
+
+        let datagram = ecn::recv_with_ecn(socket, &mut bufs[0]).await?;
+        Ok(vec![datagram])
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::super::ecn;
+    use super::EcnCodepoint;
+    use tokio::{io, net::UdpSocket};
+
+    pub async fn send_batch(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> usize {
+        for datagram in datagrams {
+            let _ = socket.send(datagram).await;
+        }
+        datagrams.len()
+    }
+
+    pub async fn recv_batch(
+        socket: &UdpSocket,
+        bufs: &mut [Vec<u8>],
+    ) -> io::Result<Vec<(usize, EcnCodepoint)>> {
+        let datagram = ecn::recv_with_ecn(socket, &mut bufs[0]).await?;
+        Ok(vec![datagram])
+    }
+}
+
+/// 在loopback上一次发出多个datagram，接收端能够在一次调用中全部读出
+#[actix_rt::test]
+async fn test_batch() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sender
+        .connect(receiver.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let datagrams: Vec<_> = (0..8u8).map(|i| vec![i; 100 + i as usize]).collect();
+    let calls = send_batch(&sender, &datagrams).await;
+    assert!(calls >= 1 && calls <= datagrams.len());
+
+    let mut bufs = vec![vec![0u8; 1024]; BATCH_SIZE];
+    let mut received = vec![];
+    while received.len() < datagrams.len() {
+        for (i, (n, _)) in recv_batch(&receiver, &mut bufs)
+            .await
+            .unwrap()
+            .into_iter()
+            .enumerate()
+        {
+            received.push(bufs[i][..n].to_vec());
+        }
+    }
+    assert_eq!(received, datagrams);
+}
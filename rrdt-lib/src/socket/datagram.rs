@@ -0,0 +1,149 @@
+use super::{batch, EcnCodepoint};
+use futures::{future::BoxFuture, FutureExt};
+use std::net::SocketAddr;
+use tokio::{io, net::UdpSocket};
+
+/// IPv4上UDP datagram的最大payload
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// 连接收发datagram所使用的底层传输
+///
+/// 除UDP之外，也可以是内存中的模拟网络、其他协议之上的隧道，或者包装了UDP socket用于统计的传输。
+/// 握手阶段服务端可能与多个地址交互，连接建立之后只与[`connect`](Self::connect)指定的对端通信
+pub trait AsyncDatagramSocket: Send + Sync + 'static {
+    /// 向对端发送一个datagram
+    fn send<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<usize>>;
+
+    /// 接收一个datagram，同时返回其来源地址
+    fn recv<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// 传输能够承载的最大datagram大小，路径MTU探测不会超过该大小
+    fn max_datagram_size(&self) -> usize;
+
+    /// 向指定地址发送一个datagram，只有一个对端的传输直接发往对端即可
+    fn send_to<'a>(&'a self, buf: &'a [u8], _addr: SocketAddr) -> BoxFuture<'a, io::Result<usize>> {
+        self.send(buf)
+    }
+
+    /// 将对端固定为`addr`，只有一个对端的传输什么也不需要做
+    fn connect(&self, _addr: SocketAddr) -> BoxFuture<'_, io::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// 发送一批datagram，返回使用的系统调用次数
+    ///
+    /// 单个datagram发送失败时直接跳过，由丢包检测负责重传
+    fn send_batch<'a>(&'a self, datagrams: &'a [Vec<u8>]) -> BoxFuture<'a, usize> {
+        async move {
+            for datagram in datagrams {
+                let _ = self.send(datagram).await;
+            }
+            datagrams.len()
+        }
+        .boxed()
+    }
+
+    /// 接收一批datagram，依次写入`bufs`中，返回每个datagram的长度和ECN标记
+    fn recv_batch<'a>(
+        &'a self,
+        bufs: &'a mut [Vec<u8>],
+    ) -> BoxFuture<'a, io::Result<Vec<(usize, EcnCodepoint)>>> {
+        async move {
+            let (n, _) = self.recv(&mut bufs[0]).await?;
+            Ok(vec![(n, EcnCodepoint::NotEct)])
+        }
+        .boxed()
+    }
+}
+
+/// UDP socket在Linux上使用批量收发，并从IP头部中读取ECN标记
+impl AsyncDatagramSocket for UdpSocket {
+    fn send<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        UdpSocket::send(self, buf).boxed()
+    }
+
+    fn recv<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        self.recv_from(buf).boxed()
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn max_datagram_size(&self) -> usize {
+        MAX_UDP_PAYLOAD
+    }
+
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> BoxFuture<'a, io::Result<usize>> {
+        UdpSocket::send_to(self, buf, addr).boxed()
+    }
+
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<()>> {
+        UdpSocket::connect(self, addr).boxed()
+    }
+
+    fn send_batch<'a>(&'a self, datagrams: &'a [Vec<u8>]) -> BoxFuture<'a, usize> {
+        batch::send_batch(self, datagrams).boxed()
+    }
+
+    fn recv_batch<'a>(
+        &'a self,
+        bufs: &'a mut [Vec<u8>],
+    ) -> BoxFuture<'a, io::Result<Vec<(usize, EcnCodepoint)>>> {
+        batch::recv_batch(self, bufs).boxed()
+    }
+}
+
+/// 连接可以建立在包装后的socket之上，路径MTU不会超过传输声明的大小
+#[actix_rt::test]
+async fn test_wrapped_socket() {
+    use crate::{ConnectionBuildResult, ConnectionBuilder, ConnectionListener, TransportParams};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    struct Counting(UdpSocket, Arc<AtomicUsize>);
+
+    impl AsyncDatagramSocket for Counting {
+        fn send<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<usize>> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            UdpSocket::send(&self.0, buf).boxed()
+        }
+
+        fn recv<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+            self.0.recv_from(buf).boxed()
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+
+        fn max_datagram_size(&self) -> usize {
+            1500
+        }
+    }
+
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server.local_addr().unwrap()).await.unwrap();
+
+    let listener =
+        ConnectionListener::from_socket(server).with_transport_params(TransportParams::default());
+    let server = actix_rt::spawn(async move { listener.accept().await.unwrap().unwrap() });
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let result = ConnectionBuilder::from_socket(Counting(client, sent.clone()))
+        .build()
+        .await
+        .unwrap();
+    let ConnectionBuildResult::Connection(conn) = result else {
+        panic!("unexpected compressed params");
+    };
+    let _server = server.await.unwrap();
+
+    assert!(sent.load(Ordering::Relaxed) > 0);
+    assert!(conn.mtu() <= 1500);
+}
@@ -0,0 +1,183 @@
+use tokio::{io, net::UdpSocket};
+
+/// IP头部中的ECN标记，见RFC3168
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum EcnCodepoint {
+    /// 不支持ECN
+    #[default]
+    NotEct = 0b00,
+    Ect1 = 0b01,
+    Ect0 = 0b10,
+    /// 路径上的设备发生了拥塞
+    Ce = 0b11,
+}
+
+impl EcnCodepoint {
+    /// 从IP头部的TOS / Traffic Class字段中提取ECN标记
+    pub fn from_bits(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b01 => Self::Ect1,
+            0b10 => Self::Ect0,
+            0b11 => Self::Ce,
+            _ => Self::NotEct,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(super) use imp::control_ecn;
+
+/// 为发出的packet标记ECT(0)，并开启接收ECN标记
+///
+/// 不支持ECN的平台上什么也不做
+pub fn enable_ecn(socket: &UdpSocket) -> io::Result<()> {
+    imp::set_ecn_codepoint(socket, EcnCodepoint::Ect0)?;
+    imp::set_recv_ecn(socket)
+}
+
+/// 接收一个datagram，同时返回其IP头部中的ECN标记
+pub async fn recv_with_ecn(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, EcnCodepoint)> {
+    imp::recv_with_ecn(socket, buf).await
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::EcnCodepoint;
+    use crate::socket::setsockopt;
+    use std::{mem, os::fd::AsRawFd};
+    use tokio::{
+        io::{self, Interest},
+        net::UdpSocket,
+    };
+
+    pub fn set_ecn_codepoint(socket: &UdpSocket, codepoint: EcnCodepoint) -> io::Result<()> {
+        let value = codepoint as libc::c_int;
+        if socket.local_addr()?.is_ipv4() {
+            setsockopt(socket, libc::IPPROTO_IP, libc::IP_TOS, value)
+        } else {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, value)?;
+            // 双栈socket上发往IPv4地址的packet使用的是IPv4的选项，失败时忽略即可
+            let _ = setsockopt(socket, libc::IPPROTO_IP, libc::IP_TOS, value);
+            Ok(())
+        }
+    }
+
+    pub fn set_recv_ecn(socket: &UdpSocket) -> io::Result<()> {
+        if socket.local_addr()?.is_ipv4() {
+            setsockopt(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)
+        } else {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+            let _ = setsockopt(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1);
+            Ok(())
+        }
+    }
+
+    /// 通过`recvmsg`接收datagram，并从control message中解析出ECN标记
+    fn recvmsg(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, EcnCodepoint)> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // control message需要按cmsghdr对齐
+        let mut control = [0u64; 8];
+
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = mem::size_of_val(&control) as _;
+
+        let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut hdr, 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((n as usize, control_ecn(&hdr)))
+    }
+
+    /// 从`recvmsg`收到的control message中解析出ECN标记
+    pub fn control_ecn(hdr: &libc::msghdr) -> EcnCodepoint {
+        let mut ecn = EcnCodepoint::NotEct;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(hdr) };
+        while !cmsg.is_null() {
+            let (level, ty) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            match (level, ty) {
+                // IPv4的TOS只有一个字节
+                (libc::IPPROTO_IP, libc::IP_TOS) => {
+                    ecn = EcnCodepoint::from_bits(unsafe { *data });
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let tclass = unsafe { (data as *const libc::c_int).read_unaligned() };
+                    ecn = EcnCodepoint::from_bits(tclass as u8);
+                }
+                _ => {}
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(hdr, cmsg) };
+        }
+
+        ecn
+    }
+
+    pub async fn recv_with_ecn(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, EcnCodepoint)> {
+        socket
+            .async_io(Interest::READABLE, || recvmsg(socket, buf))
+            .await
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::EcnCodepoint;
+    use tokio::{io, net::UdpSocket};
+
+    pub fn set_ecn_codepoint(_socket: &UdpSocket, _codepoint: EcnCodepoint) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn set_recv_ecn(_socket: &UdpSocket) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub async fn recv_with_ecn(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, EcnCodepoint)> {
+        let n = socket.recv(buf).await?;
+        Ok((n, EcnCodepoint::NotEct))
+    }
+}
+
+/// 在loopback上将发送端的packet标记为CE，模拟路径上发生了拥塞
+#[cfg(target_os = "linux")]
+#[actix_rt::test]
+async fn test_recv_ce() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    enable_ecn(&receiver).unwrap();
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sender
+        .connect(receiver.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 16];
+
+    imp::set_ecn_codepoint(&sender, EcnCodepoint::Ect0).unwrap();
+    sender.send(b"ect0").await.unwrap();
+    let (n, ecn) = recv_with_ecn(&receiver, &mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ect0");
+    assert_eq!(ecn, EcnCodepoint::Ect0);
+
+    imp::set_ecn_codepoint(&sender, EcnCodepoint::Ce).unwrap();
+    sender.send(b"ce").await.unwrap();
+    let (n, ecn) = recv_with_ecn(&receiver, &mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ce");
+    assert_eq!(ecn, EcnCodepoint::Ce);
+}
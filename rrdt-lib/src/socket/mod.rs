@@ -0,0 +1,35 @@
+mod batch;
+mod datagram;
+mod ecn;
+mod mtu;
+
+pub use batch::BATCH_SIZE;
+pub use datagram::AsyncDatagramSocket;
+pub use ecn::{enable_ecn, EcnCodepoint};
+pub use mtu::enable_pmtu_probe;
+
+#[cfg(target_os = "linux")]
+fn setsockopt(
+    socket: &tokio::net::UdpSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> tokio::io::Result<()> {
+    use std::{mem, os::fd::AsRawFd};
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        Err(tokio::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
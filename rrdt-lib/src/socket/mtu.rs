@@ -0,0 +1,49 @@
+use tokio::{io, net::UdpSocket};
+
+/// 为发出的datagram设置DF位，并且不使用内核维护的路径MTU，由连接自行探测（DPLPMTUD，见RFC8899）
+///
+/// 超过出口接口MTU的datagram会直接发送失败而不会被分片。不支持的平台上什么也不做
+pub fn enable_pmtu_probe(socket: &UdpSocket) -> io::Result<()> {
+    imp::enable_pmtu_probe(socket)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use crate::socket::setsockopt;
+    use tokio::{io, net::UdpSocket};
+
+    pub fn enable_pmtu_probe(socket: &UdpSocket) -> io::Result<()> {
+        if socket.local_addr()?.is_ipv4() {
+            setsockopt(
+                socket,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            )
+        } else {
+            setsockopt(
+                socket,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            )?;
+            // 双栈socket上发往IPv4地址的packet使用的是IPv4的选项，失败时忽略即可
+            let _ = setsockopt(
+                socket,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use tokio::{io, net::UdpSocket};
+
+    pub fn enable_pmtu_probe(_socket: &UdpSocket) -> io::Result<()> {
+        Ok(())
+    }
+}
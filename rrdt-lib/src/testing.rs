@@ -0,0 +1,506 @@
+//! 用于测试的进程内网络模拟
+//!
+//! [`SimNetwork`]在内存中连接两个端点，两个方向可以分别设置丢包、带宽、延迟、抖动、乱序、重复和损坏。
+//! 所有计时都基于tokio的时钟，随机数由种子决定，配合`tokio::time::pause`即可得到确定的测试结果。
+//! [`simulate`]在单线程的运行时上以暂停的时钟运行整个场景，同样的种子和场景总是得到同样的结果
+
+use crate::{
+    socket::{AsyncDatagramSocket, EcnCodepoint},
+    TransportConfig,
+};
+use futures::{future::BoxFuture, FutureExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{io, sync::Notify, time::Instant};
+
+/// 单个方向上的链路特性，默认是一条没有任何损伤的理想链路
+#[derive(Debug, Clone, Default)]
+pub struct LinkConfig {
+    /// 丢包率
+    pub loss: f64,
+    /// 带宽，单位为字节每秒，`None`表示不限制
+    pub bandwidth: Option<u64>,
+    /// 瓶颈处最多排队的字节数，超出时丢弃新到达的datagram，`None`表示不限制
+    pub buffer: Option<usize>,
+    /// 单向传播延迟
+    pub latency: Duration,
+    /// 在传播延迟之上额外增加的随机延迟的最大值
+    pub jitter: Duration,
+    /// datagram被额外推迟一个传播延迟的概率，使其晚于之后发出的datagram到达
+    pub reorder: f64,
+    /// datagram被复制一份发送的概率
+    pub duplicate: f64,
+    /// datagram中有一位被翻转的概率
+    ///
+    /// 未加密的连接无法发现损坏，测试损坏时应当设置PSK或使用Noise握手
+    pub corrupt: f64,
+    /// 链路能够承载的最大datagram大小，超过的datagram会被丢弃，`None`表示不限制
+    pub mtu: Option<usize>,
+    /// datagram被路径上的设备标记为CE的概率，其余datagram以ECT(0)到达
+    pub ce: f64,
+}
+
+impl LinkConfig {
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn with_bandwidth(mut self, bandwidth: u64) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = Some(buffer);
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_reorder(mut self, reorder: f64) -> Self {
+        self.reorder = reorder;
+        self
+    }
+
+    pub fn with_duplicate(mut self, duplicate: f64) -> Self {
+        self.duplicate = duplicate;
+        self
+    }
+
+    pub fn with_corrupt(mut self, corrupt: f64) -> Self {
+        self.corrupt = corrupt;
+        self
+    }
+
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    pub fn with_ce(mut self, ce: f64) -> Self {
+        self.ce = ce;
+        self
+    }
+}
+
+/// 链路上各方向的统计信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+    pub duplicated: u64,
+    pub corrupted: u64,
+    pub ce_marked: u64,
+}
+
+/// 连接客户端和服务端两个端点的模拟网络
+///
+/// 链路特性可以在任意时刻修改，只影响之后发出的datagram
+pub struct SimNetwork {
+    uplink: Arc<Mutex<Link>>,
+    downlink: Arc<Mutex<Link>>,
+    client: SimSocket,
+    server: SimSocket,
+}
+
+impl SimNetwork {
+    /// 创建两个方向都是理想链路的网络，`seed`决定所有随机行为
+    pub fn new(seed: u64) -> Self {
+        let client_addr = SocketAddr::from(([10, 0, 0, 1], 4433));
+        let server_addr = SocketAddr::from(([10, 0, 0, 2], 4433));
+        let client_inbox = Arc::new(Inbox::default());
+        let server_inbox = Arc::new(Inbox::default());
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let uplink = Arc::new(Mutex::new(Link::new(
+            StdRng::seed_from_u64(rng.gen()),
+            server_inbox.clone(),
+            client_addr,
+        )));
+        let downlink = Arc::new(Mutex::new(Link::new(
+            StdRng::seed_from_u64(rng.gen()),
+            client_inbox.clone(),
+            server_addr,
+        )));
+
+        Self {
+            client: SimSocket {
+                local: client_addr,
+                link: uplink.clone(),
+                inbox: client_inbox,
+            },
+            server: SimSocket {
+                local: server_addr,
+                link: downlink.clone(),
+                inbox: server_inbox,
+            },
+            uplink,
+            downlink,
+        }
+    }
+
+    /// 设置客户端到服务端方向的链路特性
+    pub fn set_uplink(&self, config: LinkConfig) {
+        self.uplink.lock().unwrap().config = config;
+    }
+
+    /// 设置服务端到客户端方向的链路特性
+    pub fn set_downlink(&self, config: LinkConfig) {
+        self.downlink.lock().unwrap().config = config;
+    }
+
+    pub fn uplink_stats(&self) -> LinkStats {
+        self.uplink.lock().unwrap().stats.clone()
+    }
+
+    pub fn downlink_stats(&self) -> LinkStats {
+        self.downlink.lock().unwrap().stats.clone()
+    }
+
+    /// 客户端使用的socket
+    pub fn client(&self) -> SimSocket {
+        self.client.clone()
+    }
+
+    /// 服务端使用的socket
+    pub fn server(&self) -> SimSocket {
+        self.server.clone()
+    }
+}
+
+/// 一次模拟运行的上下文，所有随机行为都由同一个种子派生
+pub struct Simulation {
+    network: SimNetwork,
+    client_seed: u64,
+    server_seed: u64,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            network: SimNetwork::new(rng.gen()),
+            client_seed: rng.gen(),
+            server_seed: rng.gen(),
+        }
+    }
+
+    pub fn network(&self) -> &SimNetwork {
+        &self.network
+    }
+
+    /// 客户端应当使用的传输配置，其中的随机数种子由模拟的种子派生
+    pub fn client_config(&self) -> TransportConfig {
+        TransportConfig::default().with_seed(self.client_seed)
+    }
+
+    /// 服务端应当使用的传输配置
+    pub fn server_config(&self) -> TransportConfig {
+        TransportConfig::default().with_seed(self.server_seed)
+    }
+}
+
+/// 在单线程的运行时上以暂停的时钟运行一个场景，没有其他任务可以执行时时钟直接跳到下一个定时器
+///
+/// 场景中的连接需要使用[`Simulation`]给出的配置。Noise握手的临时密钥仍然来自系统熵源，
+/// 只会改变密文的内容，不影响运行的过程
+#[cfg(any(test, feature = "testing"))]
+pub fn simulate<F, Fut>(seed: u64, scenario: F) -> Fut::Output
+where
+    F: FnOnce(Simulation) -> Fut,
+    Fut: std::future::Future,
+{
+    // 运行时创建时时钟就必须是暂停的，否则定时器的起点会带上真实时间的偏差
+    let system = actix_rt::System::with_tokio_rt(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    });
+    system.block_on(scenario(Simulation::new(seed)))
+}
+
+/// 模拟网络中的一个端点
+#[derive(Clone)]
+pub struct SimSocket {
+    local: SocketAddr,
+    /// 本端发出的datagram经过的链路
+    link: Arc<Mutex<Link>>,
+    /// 本端等待接收的datagram
+    inbox: Arc<Inbox>,
+}
+
+impl AsyncDatagramSocket for SimSocket {
+    fn send<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        self.link.lock().unwrap().send(buf, Instant::now());
+        async move { Ok(buf.len()) }.boxed()
+    }
+
+    fn recv<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        async move {
+            let (datagram, from, _) = self.inbox.recv().await;
+            let n = datagram.len().min(buf.len());
+            buf[..n].copy_from_slice(&datagram[..n]);
+            Ok((n, from))
+        }
+        .boxed()
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn max_datagram_size(&self) -> usize {
+        u16::MAX as usize
+    }
+
+    fn recv_batch<'a>(
+        &'a self,
+        bufs: &'a mut [Vec<u8>],
+    ) -> BoxFuture<'a, io::Result<Vec<(usize, EcnCodepoint)>>> {
+        async move {
+            let (datagram, _, ecn) = self.inbox.recv().await;
+            let n = datagram.len().min(bufs[0].len());
+            bufs[0][..n].copy_from_slice(&datagram[..n]);
+            Ok(vec![(n, ecn)])
+        }
+        .boxed()
+    }
+}
+
+/// 单个方向的链路
+struct Link {
+    config: LinkConfig,
+    rng: StdRng,
+    stats: LinkStats,
+    /// 瓶颈处排队的datagram全部发出的时间
+    busy_until: Option<Instant>,
+    /// 对端的接收队列
+    inbox: Arc<Inbox>,
+    /// 发送端的地址
+    from: SocketAddr,
+}
+
+impl Link {
+    fn new(rng: StdRng, inbox: Arc<Inbox>, from: SocketAddr) -> Self {
+        Self {
+            config: LinkConfig::default(),
+            rng,
+            stats: LinkStats::default(),
+            busy_until: None,
+            inbox,
+            from,
+        }
+    }
+
+    fn send(&mut self, datagram: &[u8], now: Instant) {
+        self.stats.sent += 1;
+
+        if self.config.mtu.is_some_and(|mtu| datagram.len() > mtu)
+            || self.rng.gen_bool(self.config.loss)
+        {
+            self.stats.lost += 1;
+            return;
+        }
+
+        // 按带宽依次发出，队列过长时尾部丢弃
+        let mut departure = now;
+        if let Some(bandwidth) = self.config.bandwidth {
+            let start = self.busy_until.map_or(now, |busy| busy.max(now));
+            if let Some(buffer) = self.config.buffer {
+                let queued = (start - now).as_secs_f64() * bandwidth as f64;
+                if queued + datagram.len() as f64 > buffer as f64 {
+                    self.stats.lost += 1;
+                    return;
+                }
+            }
+            departure = start + Duration::from_secs_f64(datagram.len() as f64 / bandwidth as f64);
+            self.busy_until = Some(departure);
+        }
+
+        let ecn = if self.rng.gen_bool(self.config.ce) {
+            self.stats.ce_marked += 1;
+            EcnCodepoint::Ce
+        } else {
+            EcnCodepoint::Ect0
+        };
+
+        let copies = if self.rng.gen_bool(self.config.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut datagram = datagram.to_vec();
+            if !datagram.is_empty() && self.rng.gen_bool(self.config.corrupt) {
+                let bit = self.rng.gen_range(0..datagram.len() * 8);
+                datagram[bit / 8] ^= 1 << (bit % 8);
+                self.stats.corrupted += 1;
+            }
+
+            let mut arrival = departure + self.config.latency;
+            if !self.config.jitter.is_zero() {
+                arrival += self.config.jitter.mul_f64(self.rng.gen());
+            }
+            if self.rng.gen_bool(self.config.reorder) {
+                arrival += self.config.latency;
+            }
+
+            self.stats.delivered += 1;
+            self.inbox.push(arrival, datagram, self.from, ecn);
+        }
+    }
+}
+
+/// 按到达时间排序的接收队列，同时到达的datagram按发出的顺序排列
+#[derive(Default)]
+struct Inbox {
+    queue: Mutex<InboxQueue>,
+    notify: Notify,
+}
+
+/// 到达时间、发出顺序、datagram、来源地址及ECN标记
+type Delivery = (Instant, u64, Vec<u8>, SocketAddr, EcnCodepoint);
+
+#[derive(Default)]
+struct InboxQueue {
+    heap: BinaryHeap<Reverse<Delivery>>,
+    seq: u64,
+}
+
+impl Inbox {
+    fn push(&self, arrival: Instant, datagram: Vec<u8>, from: SocketAddr, ecn: EcnCodepoint) {
+        let mut queue = self.queue.lock().unwrap();
+        let seq = queue.seq;
+        queue.seq += 1;
+        queue
+            .heap
+            .push(Reverse((arrival, seq, datagram, from, ecn)));
+        drop(queue);
+
+        self.notify.notify_one();
+    }
+
+    async fn recv(&self) -> (Vec<u8>, SocketAddr, EcnCodepoint) {
+        loop {
+            let next = {
+                let mut queue = self.queue.lock().unwrap();
+                match queue.heap.peek() {
+                    Some(Reverse((arrival, ..))) if *arrival <= Instant::now() => {
+                        let Reverse((_, _, datagram, from, ecn)) = queue.heap.pop().unwrap();
+                        return (datagram, from, ecn);
+                    }
+                    Some(Reverse((arrival, ..))) => Some(*arrival),
+                    None => None,
+                }
+            };
+
+            match next {
+                Some(arrival) => {
+                    // 固定分支的顺序，避免引入额外的随机性
+                    tokio::select! {
+                        biased;
+                        _ = tokio::time::sleep_until(arrival) => {}
+                        _ = self.notify.notified() => {}
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+/// 在有损伤的链路上传输数据，依靠重传完整地收到所有数据，同样的种子两次运行的过程完全相同
+#[test]
+fn test_lossy_transfer() {
+    use crate::{ConnectionBuildResult, ConnectionBuilder, ConnectionListener, TransportParams};
+
+    let run = || {
+        simulate(7, |sim| async move {
+            let network = sim.network();
+            let data: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+
+            let listener = ConnectionListener::from_socket(network.server())
+                .with_psk("sim")
+                .with_config(sim.server_config())
+                .with_transport_params(TransportParams::default().with_streams(1));
+            let sent = data.clone();
+            let server = actix_rt::spawn(async move {
+                let mut conn = listener.accept().await.unwrap().unwrap();
+                let mut stream = conn.open().await;
+                stream.send(&sent).await.unwrap();
+                stream.wrote();
+                conn
+            });
+
+            let result = ConnectionBuilder::from_socket(network.client())
+                .with_psk("sim")
+                .with_config(sim.client_config())
+                .build()
+                .await
+                .unwrap();
+            let ConnectionBuildResult::Connection(mut conn) = result else {
+                panic!("unexpected compressed params");
+            };
+
+            // 握手完成后再加入损伤，握手packet丢失后要等超时才会重传
+            let link = LinkConfig::default()
+                .with_loss(0.05)
+                .with_bandwidth(10 << 20)
+                .with_latency(Duration::from_millis(20))
+                .with_jitter(Duration::from_millis(5))
+                .with_reorder(0.02)
+                .with_duplicate(0.02)
+                .with_corrupt(0.01)
+                .with_mtu(1400);
+            network.set_uplink(link.clone());
+            network.set_downlink(link);
+
+            let start = Instant::now();
+            let mut stream = conn.accept().await.unwrap();
+            let mut received = vec![];
+            let mut buf = vec![0u8; 65536];
+            loop {
+                let n = stream.recv(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                received.extend_from_slice(&buf[..n]);
+            }
+            let server = server.await.unwrap();
+
+            assert!(received == data, "data mismatch");
+            assert!(network.downlink_stats().lost > 0);
+            assert!(conn.mtu() <= 1400);
+
+            (
+                conn.id(),
+                server.id(),
+                start.elapsed(),
+                conn.stats(),
+                network.uplink_stats(),
+                network.downlink_stats(),
+            )
+        })
+    };
+
+    assert_eq!(run(), run());
+}
@@ -1,11 +1,11 @@
 use rand::Rng;
 
-pub struct ChoiceIter<T> {
-    rng: rand::rngs::ThreadRng,
+pub struct ChoiceIter<T, R> {
+    rng: R,
     values: Vec<T>,
 }
 
-impl<T> Iterator for ChoiceIter<T> {
+impl<T, R: Rng> Iterator for ChoiceIter<T, R> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -19,17 +19,14 @@ impl<T> Iterator for ChoiceIter<T> {
 }
 
 pub trait Choice<T> {
-    fn choice(self) -> ChoiceIter<T>;
+    fn choice<R: Rng>(self, rng: R) -> ChoiceIter<T, R>;
 }
 
 impl<T> Choice<T> for Vec<T> {
-    /// 从数组中不重复的随机选择一个元素
+    /// 使用`rng`从数组中不重复的随机选择一个元素
     ///
     /// 当所有元素都被选择过后，会返回None
-    fn choice(self) -> ChoiceIter<T> {
-        ChoiceIter {
-            rng: rand::thread_rng(),
-            values: self,
-        }
+    fn choice<R: Rng>(self, rng: R) -> ChoiceIter<T, R> {
+        ChoiceIter { rng, values: self }
     }
 }
@@ -1,4 +1,3 @@
 pub mod choice;
 pub mod range_ext;
 pub mod range_set;
-pub mod task_guard;
@@ -27,6 +27,15 @@ impl RangeSet {
         self.pred(x.start).map_or(false, |(_, end)| end >= x.end)
     }
 
+    /// 集合中是否存在落在`x`中的元素
+    pub fn intersects(&self, x: &Range<u64>) -> bool {
+        if x.is_empty() {
+            return false;
+        }
+        self.pred(x.start).is_some_and(|(_, end)| end > x.start)
+            || self.succ(x.start).is_some_and(|(start, _)| start < x.end)
+    }
+
     pub fn insert_one(&mut self, x: u64) -> bool {
         if let Some((start, end)) = self.pred(x) {
             match end.cmp(&x) {